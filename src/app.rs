@@ -0,0 +1,620 @@
+#[cfg(feature = "sdl")]
+mod sdl;
+mod window;
+
+use crate::constraint::{CountConstraint, PathConstraint};
+use crate::density::Density;
+use crate::error::{Error, Result};
+use crate::grid::{Grid, Overlay};
+use crate::hierarchy::{self, Guide, GuideMode, SOFT_GUIDE_WEIGHT};
+use crate::inspector::Inspector;
+use crate::recorder::Recorder;
+use crate::render::{BufferRenderer, Color, Rect, Renderer};
+use crate::tileset::{TileSet, sample_hash};
+use crate::{Args, CACHE_DIR};
+use image::DynamicImage;
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const TITLE: &str = "Wave Function Collapse";
+const RECORD_SCALE: u32 = 8;
+const SAVE_PATH: &str = "grid.wfcg";
+const DEFAULT_STEPS_PER_FRAME: usize = 100;
+const MAX_STEPS_PER_FRAME: usize = 100_000;
+/// Generations a screenshot run tries before it saves a contradicting grid.
+const SCREENSHOT_ATTEMPTS: u64 = 10;
+
+const HELP: [&str; 17] = [
+    "H        toggle this help",
+    "P        pause / resume",
+    "Space    single step",
+    "Enter    run to completion",
+    "R        restart with a new seed",
+    "+ / -    double / halve steps per frame",
+    "S        save grid",
+    "C        start / stop recording",
+    "LMB      paint brush into cell",
+    "RMB drag erase region",
+    "Wheel    choose brush",
+    "K        switch pattern / color brush",
+    "E        entropy heatmap",
+    "W        propagation wavefront",
+    "I        tileset inspector",
+    "B        use inspected pattern as brush",
+    "Esc      quit",
+];
+
+/// Input of the viewer, translated from the events of a frontend.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Input {
+    Quit,
+    Key(Key),
+    MouseDown {
+        button: Button,
+        x: i32,
+        y: i32,
+    },
+    MouseUp {
+        button: Button,
+        x: i32,
+        y: i32,
+    },
+    /// Vertical scrolling, positive away from the user.
+    Wheel(i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Key {
+    /// A printable key, letters in lower case.
+    Char(char),
+    Escape,
+    Space,
+    Enter,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    F1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Button {
+    Left,
+    Right,
+}
+
+/// What a left click paints into a cell.
+#[derive(Clone, Copy)]
+enum Brush {
+    /// Collapses the cell to the pattern with this index.
+    Pattern(usize),
+    /// Restricts the cell to the patterns of the n-th color of the tileset.
+    Color(usize),
+}
+
+/// Opens the viewer with the frontend chosen by `args`, or only saves a
+/// screenshot of the finished grid if `--screenshot` is given, or only
+/// exports or saves the tileset if `--export` or `--save-tileset` is given.
+/// A screenshot run restarts on contradictions, up to [`SCREENSHOT_ATTEMPTS`]
+/// times; the last grid is saved even if it contradicts, but the run fails
+/// with it.
+pub(crate) fn run<const N: usize>(args: Args) -> Result<()>
+where
+    [(); N * N]:,
+{
+    if let Some(path) = &args.save_tileset {
+        let tileset = TileSet::<N, N>::from_samples_cached(
+            &args.samples,
+            args.extraction.symmetry,
+            Path::new(CACHE_DIR),
+        )?;
+        return tileset.save(path, sample_hash(&args.samples)?);
+    }
+    if let Some(dir) = &args.export {
+        let mut tileset = TileSet::<N, N>::from_samples_cached(
+            &args.samples,
+            args.extraction.symmetry,
+            Path::new(CACHE_DIR),
+        )?;
+        tileset.check_rules(args.prune);
+        let font = BufferRenderer::load_font(&args.font)
+            .inspect_err(|e| eprintln!("WARNING: atlas without labels: {}", e))
+            .ok();
+        return tileset.export(dir, args.scale, font);
+    }
+    let mut app = App::<N>::new(args)?;
+    if let Some(path) = app.args.screenshot.clone() {
+        let mut result = app.generate();
+        for attempt in 1..SCREENSHOT_ATTEMPTS {
+            let Err(e @ Error::Contradiction { .. }) = &result else {
+                break;
+            };
+            eprintln!("attempt {}: {}", attempt, e);
+            let seed = app
+                .args
+                .seed
+                .map_or_else(rand::random, |seed| seed.wrapping_add(attempt));
+            app.restart(seed);
+            result = app.generate();
+        }
+        app.screenshot(&path)?;
+        app.finish()?;
+        return result;
+    }
+    #[cfg(feature = "sdl")]
+    if !app.args.window {
+        return sdl::run(&mut app);
+    }
+    window::run(&mut app)
+}
+
+/// Prints a contradiction instead of failing with it: it shows in the grid,
+/// and restarting may resolve it.
+fn report_contradiction(result: Result<()>) -> Result<()> {
+    match result {
+        Err(e @ Error::Contradiction { .. }) => {
+            eprintln!("ERROR: {}", e);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// The viewer, independent of the window system that shows it.
+pub(crate) struct App<const N: usize>
+where
+    [(); N * N]:,
+{
+    n_frame: u32,
+    last_frametime: Instant,
+    frametime_buffer: VecDeque<f32>,
+    last_fps_update: Instant,
+    grid: Grid<N, N>,
+    args: Args,
+    recorder: Option<Recorder>,
+    paused: bool,
+    steps_per_frame: usize,
+    show_help: bool,
+    brush: Brush,
+    /// Cell where the right mouse button went down, see `erase_region`.
+    erase_start: Option<(usize, usize)>,
+    inspector: Inspector,
+    inspecting: bool,
+    overlay: Overlay,
+}
+
+impl<const N: usize> App<N>
+where
+    [(); N * N]:,
+{
+    fn new(args: Args) -> Result<Self> {
+        // Constraints and the density map are not saved with a grid, so they
+        // are added from the arguments on resume too. The neighborhood and
+        // the guide are restored from the save.
+        let seed = args.seed.unwrap_or_else(rand::random);
+        let mut grid = match &args.resume {
+            Some(path) => Grid::load(path)?,
+            None => {
+                let mut tileset = TileSet::from_samples_cached(
+                    &args.samples,
+                    args.extraction.symmetry,
+                    Path::new(CACHE_DIR),
+                )?;
+                tileset.check_rules(args.prune);
+                let mut grid = Grid::with_seed(tileset, args.width, args.height, seed)?;
+                grid.set_periodic(args.periodic);
+                grid.set_neighborhood(args.neighborhood);
+                grid
+            }
+        };
+        let (width, height) = (grid.width(), grid.height());
+        for spec in &args.counts {
+            let count = CountConstraint::new(spec, &grid.tileset, width * height)?;
+            report_contradiction(grid.add_constraint(Box::new(count)))?;
+        }
+        if !args.walkable.is_empty() {
+            let path = PathConstraint::new(
+                &args.walkable,
+                &args.endpoints,
+                &grid.tileset,
+                (width, height),
+            )?;
+            report_contradiction(grid.add_constraint(Box::new(path)))?;
+        }
+        if let Some(path) = &args.density {
+            let density =
+                Density::from_image(path, &grid.tileset, (width, height), args.density_strength)?;
+            grid.set_density(density);
+        }
+        if let Some(factor) = args.coarse
+            && args.resume.is_none()
+        {
+            let coarse = hierarchy::generate_coarse::<N>(
+                &args.samples,
+                args.extraction.symmetry,
+                factor,
+                (width, height),
+                args.periodic,
+                seed,
+            )?;
+            println!(
+                "Coarse layout: {}x{} cells from {} patterns",
+                coarse.width(),
+                coarse.height(),
+                coarse.tileset.len()
+            );
+            let mode = if args.hard_guide {
+                GuideMode::Hard
+            } else {
+                GuideMode::Soft(SOFT_GUIDE_WEIGHT)
+            };
+            let guide = Guide::from_coarse(&coarse, factor, width, height, mode);
+            report_contradiction(grid.set_guide(guide))?;
+        }
+
+        let mut app = App {
+            last_frametime: Instant::now(),
+            frametime_buffer: VecDeque::new(),
+            last_fps_update: Instant::now(),
+            grid,
+            n_frame: 0,
+            args,
+            recorder: None,
+            paused: false,
+            steps_per_frame: DEFAULT_STEPS_PER_FRAME,
+            show_help: false,
+            brush: Brush::Pattern(0),
+            erase_start: None,
+            inspector: Inspector::new(),
+            inspecting: false,
+            overlay: Overlay::default(),
+        };
+        if app.args.record.is_some() {
+            app.toggle_recording()?;
+        }
+        Ok(app)
+    }
+
+    /// Initial window size: fits the grid, but has room for the overlay and
+    /// the inspector.
+    fn window_size(&self) -> (u32, u32) {
+        let width = (self.grid.width() as u32 * self.args.scale).max(800);
+        let height = (self.grid.height() as u32 * self.args.scale).max(600);
+        (width, height)
+    }
+
+    /// Starts recording the generation, or stops and saves a running recording.
+    fn toggle_recording(&mut self) -> Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(&mut self.grid),
+            None => {
+                let output = self
+                    .args
+                    .record
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from("recording.gif"));
+                let mut recorder = Recorder::new(output, self.args.record_every, RECORD_SCALE);
+                recorder.start(&mut self.grid);
+                self.recorder = Some(recorder);
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs one observation and feeds the recorder, if any.
+    fn try_step(&mut self) -> Result<()> {
+        let result = self.grid.collapse_step();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.update(&mut self.grid);
+        }
+        result
+    }
+
+    /// Like [`App::try_step`], but reports a contradiction once, when it
+    /// arises, instead of failing with it.
+    fn step(&mut self) {
+        if self.grid.has_contradiction() {
+            return;
+        }
+        if let Err(e) = self.try_step() {
+            eprintln!("ERROR: {}", e);
+        }
+    }
+
+    /// Steps until the grid is collapsed, failing on a contradiction without
+    /// reporting it.
+    fn generate(&mut self) -> Result<()> {
+        while !self.grid.is_collapsed() {
+            self.try_step()?;
+        }
+        Ok(())
+    }
+
+    fn run_to_completion(&mut self) {
+        while !self.grid.is_collapsed() && !self.grid.has_contradiction() {
+            self.step();
+        }
+    }
+
+    fn restart(&mut self, seed: u64) {
+        self.grid.restart_with_seed(seed);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.update(&mut self.grid);
+        }
+        println!("Restarted with seed {}", seed);
+    }
+
+    /// Returns the cell under the window position `(x, y)`, if any.
+    fn cell_at(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let scale = self.args.scale as i32;
+        let (x, y) = (x / scale, y / scale);
+        (x >= 0 && y >= 0 && (x as usize) < self.grid.width() && (y as usize) < self.grid.height())
+            .then_some((x as usize, y as usize))
+    }
+
+    /// Applies the current brush to a cell and pauses, so the effect of the
+    /// propagation stays visible.
+    fn paint(&mut self, (x, y): (usize, usize)) {
+        let index = x + y * self.grid.width();
+        let result = match self.brush {
+            Brush::Pattern(tile) => self.grid.set_tile(index, tile),
+            Brush::Color(color) => {
+                let color = self.grid.tileset.colors()[color];
+                self.grid.constrain_color(index, color)
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("ERROR: {}", e);
+        }
+        self.paused = true;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.update(&mut self.grid);
+        }
+    }
+
+    /// Erases the rectangle spanned by two cells back to uncollapsed.
+    fn erase_region(&mut self, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) {
+        let width = self.grid.width();
+        let cells = (y0.min(y1)..=y0.max(y1))
+            .flat_map(|y| (x0.min(x1)..=x0.max(x1)).map(move |x| x + y * width));
+        self.grid.erase(cells);
+        self.paused = true;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.update(&mut self.grid);
+        }
+    }
+
+    /// Moves the brush `delta` entries through the patterns or colors.
+    fn cycle_brush(&mut self, delta: i32) {
+        let step =
+            |index: usize, len: usize| (index as i32 + delta).rem_euclid(len as i32) as usize;
+        self.brush = match self.brush {
+            Brush::Pattern(tile) => Brush::Pattern(step(tile, self.grid.tileset.len())),
+            Brush::Color(color) => Brush::Color(step(color, self.grid.tileset.colors().len())),
+        };
+    }
+
+    /// Reacts to one input and returns whether the viewer keeps running.
+    fn handle(&mut self, input: Input) -> bool {
+        let tile_count = self.grid.tileset.len();
+        if self.inspecting
+            && let Input::Key(key) = input
+            && self.inspector.handle_key(key, tile_count)
+        {
+            return true;
+        }
+        match input {
+            Input::Quit | Input::Key(Key::Escape) => return false,
+
+            Input::Key(Key::Space) => {
+                self.paused = true;
+                self.step();
+            }
+
+            Input::Key(Key::Char('p')) => self.paused = !self.paused,
+
+            Input::Key(Key::Enter) => self.run_to_completion(),
+
+            Input::Key(Key::Char('r')) => self.restart(rand::random()),
+
+            Input::Key(Key::Char('+' | '=')) => {
+                self.steps_per_frame = (self.steps_per_frame * 2).min(MAX_STEPS_PER_FRAME)
+            }
+
+            Input::Key(Key::Char('-')) => self.steps_per_frame = (self.steps_per_frame / 2).max(1),
+
+            Input::Key(Key::Char('h') | Key::F1) => self.show_help = !self.show_help,
+
+            Input::Key(Key::Char('s')) => match self.grid.save(Path::new(SAVE_PATH)) {
+                Ok(()) => println!("Saved grid to {}", SAVE_PATH),
+                Err(e) => eprintln!("ERROR: {}", e),
+            },
+
+            Input::Key(Key::Char('c')) => {
+                if let Err(e) = self.toggle_recording() {
+                    eprintln!("ERROR: {}", e);
+                }
+            }
+
+            Input::Key(Key::Char('k')) => {
+                self.brush = match self.brush {
+                    Brush::Pattern(_) => Brush::Color(0),
+                    Brush::Color(_) => Brush::Pattern(0),
+                }
+            }
+
+            Input::Key(Key::Char('e')) => self.overlay.entropy = !self.overlay.entropy,
+
+            Input::Key(Key::Char('w')) => self.overlay.wavefront = !self.overlay.wavefront,
+
+            Input::Key(Key::Char('i')) => self.inspecting = !self.inspecting,
+
+            Input::Key(Key::Char('b')) => self.brush = Brush::Pattern(self.inspector.selected()),
+
+            Input::Wheel(y) if self.inspecting => {
+                self.inspector.scroll(-y.signum() as isize, tile_count)
+            }
+
+            Input::Wheel(y) => self.cycle_brush(y.signum()),
+
+            Input::MouseDown {
+                button: Button::Left,
+                x,
+                y,
+            } if self.inspecting => self.inspector.click(x, y, tile_count, (N, N)),
+
+            // The inspector covers the grid, so other clicks must not paint or
+            // erase the cells below it, nor finish an erase begun before.
+            Input::MouseDown { .. } | Input::MouseUp { .. } if self.inspecting => {
+                self.erase_start = None
+            }
+
+            Input::MouseDown { button, x, y } => match (button, self.cell_at(x, y)) {
+                (Button::Left, Some(cell)) => self.paint(cell),
+                (Button::Right, cell) => self.erase_start = cell,
+                _ => {}
+            },
+
+            Input::MouseUp {
+                button: Button::Right,
+                x,
+                y,
+            } => {
+                if let (Some(start), Some(end)) = (self.erase_start.take(), self.cell_at(x, y)) {
+                    self.erase_region(start, end);
+                }
+            }
+
+            _ => {}
+        }
+        true
+    }
+
+    /// Draws one frame and advances the generation unless paused.
+    fn frame(&mut self, renderer: &mut dyn Renderer) {
+        renderer.clear(Color::BLACK);
+        self.draw_scene(renderer);
+        self.display_fps(renderer);
+        self.draw_overlay(renderer);
+    }
+
+    fn display_fps(&mut self, renderer: &mut dyn Renderer) {
+        self.n_frame += 1;
+        let now = Instant::now();
+        let frametime = now.duration_since(self.last_frametime);
+        self.last_frametime = now;
+
+        // Maintain a buffer of the last 100 frame times
+        if self.frametime_buffer.len() > 100 {
+            self.frametime_buffer.pop_back();
+        }
+        self.frametime_buffer
+            .push_front(frametime.as_secs_f32() * 1000.0);
+
+        // Calculate the average frame time
+        let frametime_avg =
+            self.frametime_buffer.iter().sum::<f32>() / self.frametime_buffer.len() as f32;
+
+        // Print FPS every second
+        if self.last_fps_update.elapsed() >= Duration::from_secs(1) {
+            println!("FPS: {:.2}", 1000.0 / frametime_avg);
+            self.last_fps_update = now;
+        }
+
+        // Render FPS to the top-left corner
+        let fps_text = format!("FPS: {:.2}", 1000.0 / frametime_avg);
+        renderer.draw_text(&fps_text, 10, 10, Color::WHITE);
+    }
+
+    fn draw_scene(&mut self, renderer: &mut dyn Renderer) {
+        if self.inspecting {
+            self.inspector.draw(renderer, &self.grid.tileset);
+        } else {
+            self.grid.draw(renderer, self.args.scale, self.overlay);
+        }
+        if !self.paused {
+            for _ in 0..self.steps_per_frame {
+                self.step();
+            }
+        }
+    }
+
+    /// Draws the status line and, if enabled, the key bindings below the FPS.
+    fn draw_overlay(&mut self, renderer: &mut dyn Renderer) {
+        let state = if self.grid.has_contradiction() {
+            "contradiction"
+        } else if self.grid.is_collapsed() {
+            "finished"
+        } else if self.paused {
+            "paused"
+        } else {
+            "running"
+        };
+        let brush = match self.brush {
+            Brush::Pattern(tile) => format!("pattern {}", tile),
+            Brush::Color(color) => format!("color {}", color),
+        };
+        let status = format!(
+            "{} | {} steps/frame | brush: {} | H for help",
+            state, self.steps_per_frame, brush
+        );
+        let mut lines = vec![status.as_str()];
+        if self.show_help {
+            lines.extend(HELP);
+            let backdrop = Rect::new(5, 30, 320, 20 * lines.len() as u32 + 10);
+            renderer.fill_rect(backdrop, Color::BLACK);
+        }
+        self.draw_brush(renderer, 40 + 20 * lines.len() as i32);
+        for (i, line) in lines.iter().enumerate() {
+            renderer.draw_text(line, 10, 35 + 20 * i as i32, Color::WHITE);
+        }
+    }
+
+    /// Draws a swatch of the current brush at height `y`.
+    fn draw_brush(&self, renderer: &mut dyn Renderer, y: i32) {
+        let tileset = &self.grid.tileset;
+        match self.brush {
+            Brush::Pattern(tile) => tileset.get_tile(tile).draw(renderer, 10, y, 8),
+            Brush::Color(color) => {
+                let [r, g, b] = tileset.colors()[color].0;
+                let swatch = Rect::new(10, y, 8 * N as u32, 8 * N as u32);
+                renderer.fill_rect(swatch, Color::rgb(r, g, b));
+            }
+        }
+    }
+
+    /// Draws the grid offscreen, with the same code and overlays as the
+    /// window, and saves it as an image.
+    fn screenshot(&self, path: &Path) -> Result<()> {
+        let scale = self.args.scale;
+        let width = self.grid.width() as u32 * scale;
+        let height = self.grid.height() as u32 * scale;
+        // Finished grids need no text, so a missing font is no reason to fail.
+        let font = BufferRenderer::load_font(&self.args.font).ok();
+        let mut renderer = BufferRenderer::new(width, height, font);
+        self.grid.draw(&mut renderer, scale, self.overlay);
+        DynamicImage::from(renderer.image().clone())
+            .into_rgb8()
+            .save(path)
+            .map_err(|e| Error::image(path, e))
+    }
+
+    /// Saves a running recording and prints the statistics if requested.
+    fn finish(&mut self) -> Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish(&mut self.grid)?;
+        }
+        if self.args.stats {
+            println!("{}", self.grid.stats());
+        }
+        Ok(())
+    }
+}
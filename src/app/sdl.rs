@@ -0,0 +1,121 @@
+use super::{App, Button, Input, Key, TITLE};
+use crate::error::{Error, Result};
+use crate::render::{FONT_SIZE, SdlRenderer};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::rect::Rect;
+
+/// Shows the viewer in an SDL window until it is closed.
+pub(super) fn run<const N: usize>(app: &mut App<N>) -> Result<()>
+where
+    [(); N * N]:,
+{
+    // Initialize SDL2
+    let sdl_context = sdl2::init().map_err(Error::Window)?;
+    let ttf_context = sdl2::ttf::init().map_err(|e| Error::Window(e.to_string()))?;
+    let font = ttf_context
+        .load_font(&app.args.font, FONT_SIZE)
+        .map_err(|e| Error::decode(&app.args.font, e))?;
+    let video_subsystem = sdl_context.video().map_err(Error::Window)?;
+
+    // Create a resizable window
+    let (width, height) = app.window_size();
+    let window = video_subsystem
+        .window(TITLE, width, height)
+        .position_centered()
+        .resizable()
+        .vulkan()
+        .build()
+        .map_err(|e| Error::Window(e.to_string()))?;
+
+    // Create a canvas for rendering
+    let canvas = window
+        .into_canvas()
+        // .present_vsync()
+        .build()
+        .map_err(|e| Error::Window(e.to_string()))?;
+    let mut renderer = SdlRenderer::new(canvas, font);
+
+    // Initialize the event pump
+    let mut event_pump = sdl_context.event_pump().map_err(Error::Window)?;
+
+    // Main loop
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            // Update the canvas's viewport to match the new window size
+            if let Event::Window {
+                win_event: WindowEvent::Resized(width, height),
+                ..
+            } = event
+            {
+                renderer
+                    .canvas
+                    .set_viewport(Rect::new(0, 0, width as u32, height as u32));
+            }
+            if let Some(input) = translate(event)
+                && !app.handle(input)
+            {
+                break 'running;
+            }
+        }
+        app.frame(&mut renderer);
+        renderer.present();
+    }
+    app.finish()
+}
+
+fn translate(event: Event) -> Option<Input> {
+    match event {
+        Event::Quit { .. } => Some(Input::Quit),
+        Event::KeyDown {
+            keycode: Some(keycode),
+            ..
+        } => translate_key(keycode).map(Input::Key),
+        Event::MouseButtonDown {
+            mouse_btn, x, y, ..
+        } => translate_button(mouse_btn).map(|button| Input::MouseDown { button, x, y }),
+        Event::MouseButtonUp {
+            mouse_btn, x, y, ..
+        } => translate_button(mouse_btn).map(|button| Input::MouseUp { button, x, y }),
+        Event::MouseWheel { y, .. } => Some(Input::Wheel(y)),
+        _ => None,
+    }
+}
+
+fn translate_key(keycode: Keycode) -> Option<Key> {
+    let key = match keycode {
+        Keycode::ESCAPE => Key::Escape,
+        Keycode::SPACE => Key::Space,
+        Keycode::RETURN | Keycode::KP_ENTER => Key::Enter,
+        Keycode::TAB => Key::Tab,
+        Keycode::LEFT => Key::Left,
+        Keycode::RIGHT => Key::Right,
+        Keycode::UP => Key::Up,
+        Keycode::DOWN => Key::Down,
+        Keycode::PAGEUP => Key::PageUp,
+        Keycode::PAGEDOWN => Key::PageDown,
+        Keycode::HOME => Key::Home,
+        Keycode::END => Key::End,
+        Keycode::F1 => Key::F1,
+        Keycode::KP_PLUS => Key::Char('+'),
+        Keycode::KP_MINUS => Key::Char('-'),
+        // Keycodes of printable keys are their (lower case) characters.
+        keycode => {
+            let c = char::from_u32(keycode.into_i32() as u32)?;
+            if !c.is_ascii_graphic() {
+                return None;
+            }
+            Key::Char(c)
+        }
+    };
+    Some(key)
+}
+
+fn translate_button(button: MouseButton) -> Option<Button> {
+    match button {
+        MouseButton::Left => Some(Button::Left),
+        MouseButton::Right => Some(Button::Right),
+        _ => None,
+    }
+}
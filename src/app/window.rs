@@ -0,0 +1,162 @@
+use super::{App, Button, Input, Key, TITLE};
+use crate::error::{Error, Result};
+use crate::render::{BufferRenderer, WindowRenderer};
+use ab_glyph::FontArc;
+use std::rc::Rc;
+use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+use winit::keyboard::{Key as WinitKey, NamedKey};
+use winit::window::{Window, WindowId};
+
+/// Shows the viewer in a winit window, drawn in software through softbuffer,
+/// until it is closed.
+pub(super) fn run<const N: usize>(app: &mut App<N>) -> Result<()>
+where
+    [(); N * N]:,
+{
+    let font = BufferRenderer::load_font(&app.args.font)?;
+    let event_loop = EventLoop::new().map_err(|e| Error::Window(e.to_string()))?;
+    event_loop.set_control_flow(ControlFlow::Poll);
+    let mut frontend = Frontend {
+        app,
+        font,
+        renderer: None,
+        cursor: (0, 0),
+        result: Ok(()),
+    };
+    event_loop
+        .run_app(&mut frontend)
+        .map_err(|e| Error::Window(e.to_string()))?;
+    frontend.result?;
+    frontend.app.finish()
+}
+
+struct Frontend<'a, const N: usize>
+where
+    [(); N * N]:,
+{
+    app: &'a mut App<N>,
+    font: FontArc,
+    /// Created once the event loop is running.
+    renderer: Option<WindowRenderer>,
+    /// Last known mouse position, winit reports it separately from clicks.
+    cursor: (i32, i32),
+    /// First error that ended the event loop.
+    result: Result<()>,
+}
+
+impl<const N: usize> Frontend<'_, N>
+where
+    [(); N * N]:,
+{
+    fn fail(&mut self, event_loop: &ActiveEventLoop, error: Error) {
+        self.result = Err(error);
+        event_loop.exit();
+    }
+}
+
+impl<const N: usize> ApplicationHandler for Frontend<'_, N>
+where
+    [(); N * N]:,
+{
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.renderer.is_some() {
+            return;
+        }
+        let (width, height) = self.app.window_size();
+        let attributes = Window::default_attributes()
+            .with_title(TITLE)
+            .with_inner_size(PhysicalSize::new(width, height));
+        let renderer = event_loop
+            .create_window(attributes)
+            .map_err(|e| Error::Window(e.to_string()))
+            .and_then(|window| WindowRenderer::new(Rc::new(window), self.font.clone()));
+        match renderer {
+            Ok(renderer) => self.renderer = Some(renderer),
+            Err(e) => self.fail(event_loop, e),
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        let input = match event {
+            WindowEvent::CloseRequested => Some(Input::Quit),
+            WindowEvent::RedrawRequested => {
+                if let Some(renderer) = &mut self.renderer {
+                    renderer.begin();
+                    self.app.frame(renderer);
+                    if let Err(e) = renderer.present() {
+                        self.fail(event_loop, e);
+                    }
+                }
+                None
+            }
+            WindowEvent::KeyboardInput { event, .. } if event.state == ElementState::Pressed => {
+                translate_key(&event.logical_key).map(Input::Key)
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = (position.x as i32, position.y as i32);
+                None
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let (x, y) = self.cursor;
+                translate_button(button).map(|button| match state {
+                    ElementState::Pressed => Input::MouseDown { button, x, y },
+                    ElementState::Released => Input::MouseUp { button, x, y },
+                })
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let y = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y as f64,
+                    MouseScrollDelta::PixelDelta(position) => position.y,
+                };
+                (y != 0.0).then_some(Input::Wheel(y.signum() as i32))
+            }
+            _ => None,
+        };
+        if let Some(input) = input
+            && !self.app.handle(input)
+        {
+            event_loop.exit();
+        }
+    }
+
+    fn about_to_wait(&mut self, _: &ActiveEventLoop) {
+        if let Some(renderer) = &self.renderer {
+            renderer.window().request_redraw();
+        }
+    }
+}
+
+fn translate_key(key: &WinitKey) -> Option<Key> {
+    let key = match key {
+        WinitKey::Named(NamedKey::Escape) => Key::Escape,
+        WinitKey::Named(NamedKey::Space) => Key::Space,
+        WinitKey::Named(NamedKey::Enter) => Key::Enter,
+        WinitKey::Named(NamedKey::Tab) => Key::Tab,
+        WinitKey::Named(NamedKey::ArrowLeft) => Key::Left,
+        WinitKey::Named(NamedKey::ArrowRight) => Key::Right,
+        WinitKey::Named(NamedKey::ArrowUp) => Key::Up,
+        WinitKey::Named(NamedKey::ArrowDown) => Key::Down,
+        WinitKey::Named(NamedKey::PageUp) => Key::PageUp,
+        WinitKey::Named(NamedKey::PageDown) => Key::PageDown,
+        WinitKey::Named(NamedKey::Home) => Key::Home,
+        WinitKey::Named(NamedKey::End) => Key::End,
+        WinitKey::Named(NamedKey::F1) => Key::F1,
+        WinitKey::Character(text) => {
+            let c = text.chars().next()?.to_ascii_lowercase();
+            if c == ' ' { Key::Space } else { Key::Char(c) }
+        }
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn translate_button(button: MouseButton) -> Option<Button> {
+    match button {
+        MouseButton::Left => Some(Button::Left),
+        MouseButton::Right => Some(Button::Right),
+        _ => None,
+    }
+}
@@ -0,0 +1,277 @@
+use crate::error::{Error, Result};
+use crate::grid::Grid;
+use crate::tileset::{Extraction, Neighborhood, TileSet};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Attempts per screenshot before giving up on it.
+const MAX_ATTEMPTS: usize = 10;
+
+/// A batch file listing named runs, see `samples.toml`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Batch {
+    #[serde(rename = "run")]
+    runs: Vec<Run>,
+}
+
+/// One named run of a batch. Defaults follow the reference implementation.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Run {
+    name: String,
+    /// Sample image relative to the batch file, `samples/<name>.png` if unset.
+    sample: Option<PathBuf>,
+    n: usize,
+    width: usize,
+    height: usize,
+    symmetry: usize,
+    periodic_input: bool,
+    periodic: bool,
+    ground: bool,
+    /// Whether dead-end patterns are removed, see `TileSet::check_rules`.
+    prune: bool,
+    neighborhood: Neighborhood,
+    screenshots: usize,
+    seed: Option<u64>,
+}
+
+impl Batch {
+    fn load(config: &Path) -> Result<Self> {
+        let text = fs::read_to_string(config).map_err(|e| Error::io(config, e))?;
+        let batch: Batch = toml::from_str(&text).map_err(|e| Error::decode(config, e))?;
+        if batch.runs.iter().any(|run| run.name.is_empty()) {
+            return Err(Error::Config(format!(
+                "{}: every run needs a name",
+                config.display()
+            )));
+        }
+        Ok(batch)
+    }
+}
+
+impl Run {
+    /// The sample of the run, relative to the batch file `config`.
+    fn sample_path(&self, config: &Path) -> PathBuf {
+        let base_dir = config.parent().unwrap_or(Path::new("."));
+        base_dir.join(
+            self.sample
+                .clone()
+                .unwrap_or_else(|| Path::new("samples").join(format!("{}.png", self.name))),
+        )
+    }
+}
+
+impl Default for Run {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            sample: None,
+            n: 3,
+            width: 48,
+            height: 48,
+            symmetry: 8,
+            periodic_input: true,
+            periodic: false,
+            ground: false,
+            prune: false,
+            neighborhood: Neighborhood::Cardinal,
+            screenshots: 2,
+            seed: None,
+        }
+    }
+}
+
+/// Runs every entry of the batch file `config`, writing the outputs as
+/// `<name> <screenshot>.png` into `output_dir`. With `stats`, the generation
+/// counters of every run are printed.
+pub(crate) fn run(config: &Path, output_dir: &Path, cache_dir: &Path, stats: bool) -> Result<()> {
+    let batch = Batch::load(config)?;
+    fs::create_dir_all(output_dir).map_err(|e| Error::io(output_dir, e))?;
+
+    let mut failed = Vec::new();
+    for run in &batch.runs {
+        let sample = run.sample_path(config);
+        let result = match run.n {
+            2 => run_sample::<2>(run, &sample, output_dir, cache_dir, stats),
+            3 => run_sample::<3>(run, &sample, output_dir, cache_dir, stats),
+            4 => run_sample::<4>(run, &sample, output_dir, cache_dir, stats),
+            n => Err(Error::Config(format!(
+                "N = {} is not supported, use 2, 3 or 4",
+                n
+            ))),
+        };
+        match result {
+            Ok(done) if done == run.screenshots => {}
+            Ok(done) => failed.push(format!(
+                "{}: {} of {} screenshots contradicted",
+                run.name,
+                run.screenshots - done,
+                run.screenshots
+            )),
+            Err(e) => failed.push(format!("{}: {}", run.name, e)),
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Batch(failed))
+    }
+}
+
+/// Attempts every run of the batch file `config` `attempts` times with each
+/// [`Neighborhood`], using the same seeds, and prints how many attempts
+/// contradicted. Runs with a `seed` are compared reproducibly.
+pub(crate) fn compare(config: &Path, cache_dir: &Path, attempts: usize) -> Result<()> {
+    let batch = Batch::load(config)?;
+    println!("{:<16} {:>14} {:>14}", "run", "cardinal", "full");
+    let mut totals = [0; 2];
+    let mut failed = Vec::new();
+    for run in &batch.runs {
+        let sample = run.sample_path(config);
+        let result = match run.n {
+            2 => compare_sample::<2>(run, &sample, cache_dir, attempts),
+            3 => compare_sample::<3>(run, &sample, cache_dir, attempts),
+            4 => compare_sample::<4>(run, &sample, cache_dir, attempts),
+            n => Err(Error::Config(format!(
+                "N = {} is not supported, use 2, 3 or 4",
+                n
+            ))),
+        };
+        match result {
+            Ok(rates) => {
+                let columns: Vec<String> = rates
+                    .iter()
+                    .map(|(contradictions, seconds)| {
+                        format!("{}/{} {:.1}s", contradictions, attempts, seconds)
+                    })
+                    .collect();
+                println!("{:<16} {:>14} {:>14}", run.name, columns[0], columns[1]);
+                for (total, (contradictions, _)) in totals.iter_mut().zip(rates) {
+                    *total += contradictions;
+                }
+            }
+            Err(e) => failed.push(format!("{}: {}", run.name, e)),
+        }
+    }
+    let attempted = attempts * (batch.runs.len() - failed.len());
+    println!(
+        "{:<16} {:>14} {:>14}",
+        "total",
+        format!("{}/{}", totals[0], attempted),
+        format!("{}/{}", totals[1], attempted)
+    );
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Batch(failed))
+    }
+}
+
+/// Counts the contradictions of one run with the cardinal and with the full
+/// neighborhood, together with the seconds each took.
+fn compare_sample<const N: usize>(
+    run: &Run,
+    sample: &Path,
+    cache_dir: &Path,
+    attempts: usize,
+) -> Result<[(usize, f64); 2]>
+where
+    [(); N * N]:,
+{
+    let extraction = Extraction {
+        periodic: run.periodic_input,
+        symmetry: run.symmetry,
+    };
+    let mut tileset = TileSet::<N, N>::from_sample_cached(sample, extraction, cache_dir)?;
+    tileset.check_rules(run.prune);
+    let seed = run.seed.unwrap_or_else(rand::random);
+    let mut grid = Grid::with_seed(tileset, run.width, run.height, seed)?;
+    grid.set_periodic(run.periodic);
+    let mut rates = [(0, 0.0); 2];
+    for (neighborhood, rate) in [Neighborhood::Cardinal, Neighborhood::Full]
+        .into_iter()
+        .zip(&mut rates)
+    {
+        let start = Instant::now();
+        grid.set_neighborhood(neighborhood);
+        for attempt in 0..attempts {
+            grid.reset(seed.wrapping_add(attempt as u64));
+            let result = if run.ground {
+                grid.apply_ground()
+            } else {
+                Ok(())
+            };
+            match result.and_then(|()| grid.run_to_completion()) {
+                Ok(()) => {}
+                Err(Error::Contradiction { .. }) => rate.0 += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        rate.1 = start.elapsed().as_secs_f64();
+    }
+    Ok(rates)
+}
+
+/// Generates the screenshots of one run and returns how many succeeded.
+fn run_sample<const N: usize>(
+    run: &Run,
+    sample: &Path,
+    output_dir: &Path,
+    cache_dir: &Path,
+    stats: bool,
+) -> Result<usize>
+where
+    [(); N * N]:,
+{
+    let extraction = Extraction {
+        periodic: run.periodic_input,
+        symmetry: run.symmetry,
+    };
+    let mut tileset = TileSet::<N, N>::from_sample_cached(sample, extraction, cache_dir)?;
+    tileset.check_rules(run.prune);
+    let seed = run.seed.unwrap_or_else(rand::random);
+    println!("{}: {} patterns, seed {}", run.name, tileset.len(), seed);
+
+    let mut grid = Grid::with_seed(tileset, run.width, run.height, seed)?;
+    grid.set_periodic(run.periodic);
+    grid.set_neighborhood(run.neighborhood);
+    let mut attempt_seed = seed;
+    let mut done = 0;
+    for screenshot in 0..run.screenshots {
+        for attempt in 0..MAX_ATTEMPTS {
+            // Retries after a contradiction count as restarts in the stats.
+            if attempt == 0 {
+                grid.reset(attempt_seed);
+            } else {
+                grid.restart_with_seed(attempt_seed);
+            }
+            attempt_seed = attempt_seed.wrapping_add(1);
+            let result = if run.ground {
+                grid.apply_ground()
+            } else {
+                Ok(())
+            };
+            match result.and_then(|()| grid.run_to_completion()) {
+                Ok(()) => {
+                    let path = output_dir.join(format!("{} {}.png", run.name, screenshot));
+                    grid.render(1)
+                        .save(&path)
+                        .map_err(|e| Error::image(&path, e))?;
+                    done += 1;
+                    break;
+                }
+                Err(Error::Contradiction { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    if stats {
+        println!("{}", grid.stats());
+    }
+    Ok(done)
+}
@@ -0,0 +1,180 @@
+mod count;
+mod path;
+
+pub(crate) use count::{CountConstraint, CountSpec};
+pub(crate) use path::PathConstraint;
+
+use crate::error::{Error, Result};
+use crate::grid::{Cell, neighbor};
+use crate::tileset::{Direction, TileSet};
+use bittyset::BitSet;
+use image::Rgb;
+use std::fmt;
+use std::str::FromStr;
+
+/// A game-specific rule the generation has to follow, on top of the
+/// adjacency rules of the tileset.
+///
+/// A constraint is attached with `Grid::add_constraint` and looks at the
+/// cells through a [`Context`], where it bans options. The grid removes them
+/// and propagates the removal through the same queue as its observations. A
+/// constraint that can no longer be met fails with [`Error::Contradiction`].
+///
+/// Hooks are called again and again until none of the constraints of a grid
+/// bans anything anymore, so they have to be idempotent: banning options a
+/// cell does not have anymore is fine, and expected.
+pub(crate) trait Constraint {
+    /// Called when the constraint is added and whenever the grid starts
+    /// over or cells regain options, e.g. after a reset or an erase. State
+    /// derived from the cells has to be rebuilt here.
+    fn init(&mut self, context: &mut Context) -> Result<()>;
+
+    /// Called after cell `index` was collapsed to `tile` and the observation
+    /// was propagated, before [`Constraint::propagated`].
+    fn observed(&mut self, _context: &mut Context, _index: usize, _tile: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once a propagation settled. [`Context::changed`] holds the
+    /// cells that lost options since the start of the current step.
+    fn propagated(&mut self, context: &mut Context) -> Result<()>;
+}
+
+/// The cells of a grid as seen by a [`Constraint`], and the options it bans.
+pub(crate) struct Context<'a> {
+    cells: &'a [Cell],
+    width: usize,
+    height: usize,
+    periodic: bool,
+    changed: &'a BitSet,
+    bans: Vec<(usize, BitSet)>,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(
+        cells: &'a [Cell],
+        (width, height): (usize, usize),
+        periodic: bool,
+        changed: &'a BitSet,
+    ) -> Self {
+        Self {
+            cells,
+            width,
+            height,
+            periodic,
+            changed,
+            bans: Vec::new(),
+        }
+    }
+
+    /// Number of cells of the grid.
+    pub(crate) fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// The options cell `index` has left. Bans of the current hook are not
+    /// applied yet.
+    pub(crate) fn options(&self, index: usize) -> &BitSet {
+        &self.cells[index].options
+    }
+
+    /// The cell next to `index` in `direction`, wrapping around periodic
+    /// grids.
+    pub(crate) fn neighbor(&self, index: usize, direction: Direction) -> Option<usize> {
+        neighbor((self.width, self.height), self.periodic, index, direction)
+    }
+
+    /// Cells whose options changed since the start of the current step.
+    pub(crate) fn changed(&self) -> &BitSet {
+        self.changed
+    }
+
+    /// Removes `options` from cell `index`. Removing the last option is a
+    /// contradiction.
+    pub(crate) fn ban(&mut self, index: usize, options: &BitSet) {
+        if options
+            .iter()
+            .any(|option| self.cells[index].options.contains(option))
+        {
+            self.bans.push((index, options.clone()));
+        }
+    }
+
+    /// Removes every option but `allowed` from cell `index`.
+    pub(crate) fn restrict(&mut self, index: usize, allowed: &BitSet) {
+        let banned = BitSet::difference(&self.cells[index].options, allowed);
+        if !banned.is_empty() {
+            self.bans.push((index, banned));
+        }
+    }
+
+    /// The bans collected by the hooks, in order.
+    pub(crate) fn into_bans(self) -> Vec<(usize, BitSet)> {
+        self.bans
+    }
+}
+
+/// Patterns a constraint applies to, written `p<index>` or `#rrggbb`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// One pattern, by its index in the tileset.
+    Pattern(usize),
+    /// All patterns of a color, see `TileSet::tiles_with_color`.
+    Color(Rgb<u8>),
+}
+
+impl Target {
+    /// The patterns of `tileset` this target selects.
+    pub(crate) fn tiles<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        self,
+        tileset: &TileSet<TILE_WIDTH, TILE_HEIGHT>,
+    ) -> Result<BitSet>
+    where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        let tiles = match self {
+            Target::Pattern(index) if index < tileset.len() => [index].into_iter().collect(),
+            Target::Pattern(index) => {
+                return Err(Error::Config(format!(
+                    "pattern {} does not exist, the tileset has {}",
+                    index,
+                    tileset.len()
+                )));
+            }
+            Target::Color(color) => tileset.tiles_with_color(color),
+        };
+        if tiles.is_empty() {
+            return Err(Error::Config(format!("no pattern has color {}", self)));
+        }
+        Ok(tiles)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Pattern(index) => write!(f, "p{}", index),
+            Target::Color(Rgb([r, g, b])) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(target: &str) -> std::result::Result<Self, String> {
+        if let Some(index) = target.strip_prefix('p') {
+            Ok(Target::Pattern(
+                index.parse().map_err(|_| "invalid pattern index")?,
+            ))
+        } else if let Some(hex) = target.strip_prefix('#')
+            && hex.len() == 6
+        {
+            let rgb = u32::from_str_radix(hex, 16).map_err(|_| "invalid color")?;
+            let [_, r, g, b] = rgb.to_be_bytes();
+            Ok(Target::Color(Rgb([r, g, b])))
+        } else {
+            Err("target must be p<index> or #rrggbb".to_string())
+        }
+    }
+}
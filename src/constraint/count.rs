@@ -0,0 +1,297 @@
+use super::{Constraint, Context, Target};
+use crate::error::{Error, Result};
+use crate::tileset::TileSet;
+use bittyset::BitSet;
+use std::str::FromStr;
+
+/// A bound of a count constraint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Amount {
+    Cells(usize),
+    /// A share of all cells of the grid, in percent.
+    Percent(f64),
+}
+
+impl Amount {
+    fn cells(self, total: usize) -> usize {
+        match self {
+            Amount::Cells(cells) => cells,
+            Amount::Percent(percent) => (percent / 100.0 * total as f64).round() as usize,
+        }
+    }
+}
+
+/// A global cardinality constraint as given on the command line:
+/// `<target>=<min>..<max>`, where the target is `p<index>` for a pattern or
+/// `#rrggbb` for a color, and the bounds are cell counts or percentages of
+/// the grid. Either bound may be left out, e.g. `#3399ff=..5%` or `p12=1..1`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CountSpec {
+    pub(crate) target: Target,
+    pub(crate) min: Option<Amount>,
+    pub(crate) max: Option<Amount>,
+}
+
+impl FromStr for CountSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, String> {
+        let (target, range) = spec
+            .split_once('=')
+            .ok_or("expected <target>=<min>..<max>")?;
+        let target = target.parse()?;
+        let (min, max) = range.split_once("..").ok_or("expected <min>..<max>")?;
+        let amount = |text: &str| -> std::result::Result<Option<Amount>, String> {
+            if text.is_empty() {
+                return Ok(None);
+            }
+            let amount = match text.strip_suffix('%') {
+                Some(percent) => {
+                    Amount::Percent(percent.parse().map_err(|_| "invalid percentage")?)
+                }
+                None => Amount::Cells(text.parse().map_err(|_| "invalid count")?),
+            };
+            Ok(Some(amount))
+        };
+        Ok(Self {
+            target,
+            min: amount(min)?,
+            max: amount(max)?,
+        })
+    }
+}
+
+/// Limits how many cells of a grid end up with some patterns.
+///
+/// Cells only ever lose options between resets, so a cell that can no longer
+/// take a counted pattern never can again, and a cell left with nothing but
+/// counted patterns stays that way. Both sets are therefore kept up to date
+/// from the changed cells alone.
+#[derive(Clone, Debug)]
+pub(crate) struct CountConstraint {
+    tiles: BitSet,
+    min: usize,
+    max: usize,
+    /// Cells that may still end up with a counted pattern.
+    possible: BitSet,
+    /// Cells that end up with a counted pattern in any case.
+    definite: BitSet,
+}
+
+impl CountConstraint {
+    /// The constraint `spec` on a grid of `cells` cells using `tileset`.
+    pub(crate) fn new<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        spec: &CountSpec,
+        tileset: &TileSet<TILE_WIDTH, TILE_HEIGHT>,
+        cells: usize,
+    ) -> Result<Self>
+    where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        let tiles = spec.target.tiles(tileset)?;
+        let min = spec.min.map_or(0, |amount| amount.cells(cells));
+        let max = spec.max.map_or(cells, |amount| amount.cells(cells));
+        if min > max {
+            return Err(Error::Config(format!(
+                "minimum {} of {} is above its maximum {}",
+                min, spec.target, max
+            )));
+        }
+        Ok(Self {
+            tiles,
+            min,
+            max,
+            possible: BitSet::new(),
+            definite: BitSet::new(),
+        })
+    }
+
+    fn update(&mut self, index: usize, options: &BitSet) {
+        if !options.iter().any(|option| self.tiles.contains(option)) {
+            self.possible.remove(index);
+        } else if options.is_subset(&self.tiles) {
+            self.definite.insert(index);
+        }
+    }
+
+    /// Bans the counted patterns everywhere once the maximum is reached, and
+    /// forces them into the remaining candidates once the minimum needs all
+    /// of them. Fails if a limit can no longer be met.
+    fn enforce(&self, context: &mut Context) -> Result<()> {
+        let (possible, definite) = (self.possible.len(), self.definite.len());
+        if definite > self.max {
+            let index = self.definite.iter().last().unwrap_or(0);
+            return Err(Error::Contradiction { index });
+        }
+        if possible < self.min {
+            let index = context.changed().iter().last().unwrap_or(0);
+            return Err(Error::Contradiction { index });
+        }
+        let undecided = BitSet::difference(&self.possible, &self.definite);
+        if definite == self.max {
+            for index in undecided.iter() {
+                context.ban(index, &self.tiles);
+            }
+        } else if possible == self.min {
+            for index in undecided.iter() {
+                context.restrict(index, &self.tiles);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Constraint for CountConstraint {
+    fn init(&mut self, context: &mut Context) -> Result<()> {
+        self.possible = (0..context.len()).collect();
+        self.definite.clear();
+        for index in 0..context.len() {
+            self.update(index, context.options(index));
+        }
+        self.enforce(context)
+    }
+
+    fn propagated(&mut self, context: &mut Context) -> Result<()> {
+        for index in context.changed().iter() {
+            self.update(index, context.options(index));
+        }
+        self.enforce(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Cell;
+    use crate::tileset::Extraction;
+    use image::{Rgb, RgbImage};
+
+    /// Patterns of one pixel: p0 is red, p1 blue and p2 green.
+    fn tileset() -> TileSet<1, 1> {
+        let colors = [Rgb([255, 0, 0]), Rgb([0, 0, 255]), Rgb([0, 255, 0])];
+        let image = RgbImage::from_fn(3, 1, |x, _| colors[x as usize]);
+        TileSet::new(&image, Extraction::default()).unwrap()
+    }
+
+    fn cells(options: &[&[usize]]) -> Vec<Cell> {
+        options
+            .iter()
+            .map(|options| Cell::new(options.iter().copied().collect()))
+            .collect()
+    }
+
+    fn constraint(spec: &str, cells: usize) -> Result<CountConstraint> {
+        CountConstraint::new(&spec.parse().unwrap(), &tileset(), cells)
+    }
+
+    /// Calls `hook` on a row of `cells` and returns the bans as
+    /// `(cell, patterns)`.
+    fn run(
+        cells: &[Cell],
+        changed: &[usize],
+        hook: impl FnOnce(&mut Context) -> Result<()>,
+    ) -> Result<Vec<(usize, Vec<usize>)>> {
+        let changed: BitSet = changed.iter().copied().collect();
+        let mut context = Context::new(cells, (cells.len(), 1), false, &changed);
+        hook(&mut context)?;
+        Ok(context
+            .into_bans()
+            .into_iter()
+            .map(|(index, banned)| (index, banned.iter().collect()))
+            .collect())
+    }
+
+    fn init(spec: &str, cells: &[Cell]) -> Result<Vec<(usize, Vec<usize>)>> {
+        let mut constraint = constraint(spec, cells.len())?;
+        run(cells, &[], |context| constraint.init(context))
+    }
+
+    #[test]
+    fn parses_bounds() {
+        let spec: CountSpec = "p1=2..5".parse().unwrap();
+        assert_eq!(spec.target, Target::Pattern(1));
+        assert_eq!(spec.min, Some(Amount::Cells(2)));
+        assert_eq!(spec.max, Some(Amount::Cells(5)));
+        let spec: CountSpec = "#3399ff=..5%".parse().unwrap();
+        assert_eq!(spec.target, Target::Color(Rgb([0x33, 0x99, 0xff])));
+        assert_eq!(spec.min, None);
+        assert_eq!(spec.max, Some(Amount::Percent(5.0)));
+        assert!("p1=5".parse::<CountSpec>().is_err());
+        assert!("p1=..x".parse::<CountSpec>().is_err());
+    }
+
+    #[test]
+    fn bans_pattern_once_maximum_is_reached() {
+        let cells = cells(&[&[0], &[0], &[0, 1], &[1, 2], &[0, 2]]);
+        assert_eq!(
+            init("p0=..2", &cells).unwrap(),
+            [(2, vec![0]), (4, vec![0])]
+        );
+        assert_eq!(init("p0=..3", &cells).unwrap(), []);
+    }
+
+    #[test]
+    fn forces_pattern_once_minimum_needs_all_candidates() {
+        let cells = cells(&[&[0], &[0, 1], &[1], &[0, 2]]);
+        assert_eq!(
+            init("p0=3..", &cells).unwrap(),
+            [(1, vec![1]), (3, vec![2])]
+        );
+        assert_eq!(init("p0=2..", &cells).unwrap(), []);
+    }
+
+    #[test]
+    fn rejects_minimum_above_maximum() {
+        assert!(matches!(constraint("p0=3..2", 10), Err(Error::Config(_))));
+        assert!(matches!(
+            constraint("p0=60%..50%", 10),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn percent_bounds_are_shares_of_the_grid() {
+        let count = constraint("p0=25%..75%", 10).unwrap();
+        assert_eq!((count.min, count.max), (3, 8));
+        let cells = cells(&[&[0], &[0], &[0, 1], &[0, 1]]);
+        assert_eq!(
+            init("p0=..50%", &cells).unwrap(),
+            [(2, vec![0]), (3, vec![0])]
+        );
+    }
+
+    #[test]
+    fn color_targets_count_the_patterns_of_the_color() {
+        let constraint = constraint("#0000ff=..1", 3).unwrap();
+        assert_eq!(constraint.tiles.iter().collect::<Vec<_>>(), [1]);
+        let cells = cells(&[&[1], &[0, 1], &[1, 2]]);
+        assert_eq!(
+            init("#0000ff=..1", &cells).unwrap(),
+            [(1, vec![1]), (2, vec![1])]
+        );
+        assert!(matches!(
+            self::constraint("#123456=..1", 3),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn reports_contradictions() {
+        // Too many cells are certain to get the pattern.
+        let over = cells(&[&[0], &[1], &[0]]);
+        assert!(matches!(
+            init("p0=..1", &over),
+            Err(Error::Contradiction { index: 2 })
+        ));
+
+        // A step leaves too few cells that may get the pattern.
+        let mut count = constraint("p0=2..", 3).unwrap();
+        let before = cells(&[&[0], &[0, 1], &[1]]);
+        run(&before, &[], |context| count.init(context)).unwrap();
+        let after = cells(&[&[0], &[1], &[1]]);
+        assert!(matches!(
+            run(&after, &[1], |context| count.propagated(context)),
+            Err(Error::Contradiction { index: 1 })
+        ));
+    }
+}
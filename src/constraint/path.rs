@@ -0,0 +1,428 @@
+use super::{Constraint, Context, Target};
+use crate::error::{Error, Result};
+use crate::tileset::{Direction, TileSet};
+use bittyset::BitSet;
+
+/// Requires walkable cells to form one connected region.
+///
+/// Without endpoints, every cell that is certain to be walkable must be
+/// reachable from every other one through cells that may still be walkable.
+/// With endpoints, only those cells have to be connected. Cells whose removal
+/// would cut required cells apart are forced to be walkable.
+///
+/// The search behind this is kept between steps. Steps that only make cells
+/// required extend it, only steps that take the last walkable option from a
+/// cell it reached search again.
+#[derive(Clone, Debug)]
+pub(crate) struct PathConstraint {
+    tiles: BitSet,
+    endpoints: Vec<usize>,
+    search: Option<Search>,
+}
+
+impl PathConstraint {
+    /// Declares the patterns of `walkable` walkable and keeps the walkable
+    /// cells, or only the cells at `endpoints`, connected on a grid of
+    /// `width` x `height` cells using `tileset`.
+    pub(crate) fn new<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        walkable: &[Target],
+        endpoints: &[(usize, usize)],
+        tileset: &TileSet<TILE_WIDTH, TILE_HEIGHT>,
+        (width, height): (usize, usize),
+    ) -> Result<Self>
+    where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        let mut tiles = BitSet::new();
+        for &target in walkable {
+            tiles.union_with(&target.tiles(tileset)?);
+        }
+        if tiles.is_empty() {
+            return Err(Error::Config("no walkable patterns given".to_string()));
+        }
+        let mut indices = Vec::new();
+        for &(x, y) in endpoints {
+            if x >= width || y >= height {
+                return Err(Error::Config(format!(
+                    "endpoint {},{} is outside of the {}x{} grid",
+                    x, y, width, height
+                )));
+            }
+            indices.push(x + y * width);
+        }
+        Ok(Self {
+            tiles,
+            endpoints: indices,
+            search: None,
+        })
+    }
+
+    /// Whether cell `index` may still be walkable.
+    fn possible(tiles: &BitSet, context: &Context, index: usize) -> bool {
+        context
+            .options(index)
+            .iter()
+            .any(|option| tiles.contains(option))
+    }
+
+    /// Checks the connectivity of the required cells and forces the cells
+    /// that hold them together to be walkable. Fails if required cells are
+    /// already cut apart.
+    fn enforce(&mut self, context: &mut Context) -> Result<()> {
+        let len = context.len();
+        let possible: Vec<bool> = (0..len)
+            .map(|index| Self::possible(&self.tiles, context, index))
+            .collect();
+        let mut required = vec![false; len];
+        if self.endpoints.is_empty() {
+            for index in 0..len {
+                required[index] = possible[index] && context.options(index).is_subset(&self.tiles);
+            }
+        } else {
+            for &index in &self.endpoints {
+                required[index] = true;
+            }
+        }
+        self.search_again(context, possible, required)
+    }
+
+    /// Updates the search with the cells changed by the current step. Cells
+    /// that only became required extend the search, but if a cell it reached
+    /// can no longer be walked on, or a cell got options back, e.g. after
+    /// erasing, it searches again.
+    fn update(&mut self, context: &mut Context) -> Result<()> {
+        let Some(search) = &mut self.search else {
+            return self.enforce(context);
+        };
+        let mut search_again = false;
+        let mut newly_required = Vec::new();
+        for index in context.changed().iter() {
+            let possible = Self::possible(&self.tiles, context, index);
+            let required = if self.endpoints.is_empty() {
+                possible && context.options(index).is_subset(&self.tiles)
+            } else {
+                search.required[index]
+            };
+            search_again |= possible != search.possible[index]
+                && (possible || search.reached(index))
+                || required != search.required[index] && (!required || search.root.is_none());
+            if required && !search.required[index] {
+                newly_required.push(index);
+            }
+            search.possible[index] = possible;
+            search.required[index] = required;
+        }
+        if search_again {
+            let possible = std::mem::take(&mut search.possible);
+            let required = std::mem::take(&mut search.required);
+            return self.search_again(context, possible, required);
+        }
+        for index in newly_required {
+            if !search.reached(index) {
+                return Err(Error::Contradiction { index });
+            }
+            search.require(index);
+        }
+        self.restrict(context);
+        Ok(())
+    }
+
+    /// Replaces the search with a new one over `possible` cells, connecting
+    /// the `required` ones.
+    fn search_again(
+        &mut self,
+        context: &mut Context,
+        possible: Vec<bool>,
+        required: Vec<bool>,
+    ) -> Result<()> {
+        let len = context.len();
+        // Endpoints that cannot be walked on any more are a contradiction,
+        // just like endpoints that cannot be reached.
+        let unreachable = (0..len).find(|&index| required[index] && !possible[index]);
+        let search = Search::new(context, possible, required);
+        let unreachable = unreachable
+            .or_else(|| (0..len).find(|&index| search.required[index] && !search.reached(index)));
+        self.search = Some(search);
+        if let Some(index) = unreachable {
+            return Err(Error::Contradiction { index });
+        }
+        self.restrict(context);
+        Ok(())
+    }
+
+    fn restrict(&self, context: &mut Context) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        for index in search.cuts.iter().chain(self.endpoints.iter().copied()) {
+            context.restrict(index, &self.tiles);
+        }
+    }
+}
+
+impl Constraint for PathConstraint {
+    fn init(&mut self, context: &mut Context) -> Result<()> {
+        self.enforce(context)
+    }
+
+    fn propagated(&mut self, context: &mut Context) -> Result<()> {
+        self.update(context)
+    }
+}
+
+/// Marks the parent of the root and of cells the search has not reached.
+const NONE: usize = usize::MAX;
+
+/// A depth first search over the cells that may be walkable, starting at the
+/// first required cell, and the articulation points that separate a required
+/// cell from it (Tarjan's algorithm, iterative to cope with large grids).
+#[derive(Clone, Debug)]
+struct Search {
+    root: Option<usize>,
+    possible: Vec<bool>,
+    required: Vec<bool>,
+    /// Time at which every cell was reached, or [`NONE`].
+    discovered: Vec<usize>,
+    low: Vec<usize>,
+    parent: Vec<usize>,
+    /// Whether the search subtree of a cell holds a required cell.
+    holds_required: Vec<bool>,
+    cuts: BitSet,
+}
+
+impl Search {
+    fn new(context: &Context, possible: Vec<bool>, required: Vec<bool>) -> Self {
+        let len = context.len();
+        let mut search = Search {
+            root: required.iter().position(|&required| required),
+            possible,
+            required,
+            discovered: vec![NONE; len],
+            low: vec![0; len],
+            parent: vec![NONE; len],
+            holds_required: vec![false; len],
+            cuts: BitSet::new(),
+        };
+        let Some(root) = search.root else {
+            return search;
+        };
+        search.discovered[root] = 0;
+        let mut time = 1;
+        // (cell, next direction to follow, whether the edge to the parent
+        // was skipped). On grids of width or height 2 that wrap around, a
+        // cell neighbors its parent twice, and only one of these edges is
+        // the edge of the search tree.
+        let mut stack = vec![(root, 0, false)];
+        while let Some((cell, next, skipped_parent)) = stack.last_mut() {
+            let cell = *cell;
+            let parent = search.parent[cell];
+            if *next < Direction::VALUES.len() {
+                let direction = Direction::VALUES[*next];
+                *next += 1;
+                let Some(neighbor) = context.neighbor(cell, direction) else {
+                    continue;
+                };
+                if !search.possible[neighbor] || neighbor == cell {
+                    continue;
+                }
+                if neighbor == parent && !*skipped_parent {
+                    *skipped_parent = true;
+                    continue;
+                }
+                if search.discovered[neighbor] == NONE {
+                    search.discovered[neighbor] = time;
+                    search.low[neighbor] = time;
+                    search.parent[neighbor] = cell;
+                    time += 1;
+                    stack.push((neighbor, 0, false));
+                } else {
+                    search.low[cell] = search.low[cell].min(search.discovered[neighbor]);
+                }
+            } else {
+                stack.pop();
+                if parent != NONE {
+                    search.low[parent] = search.low[parent].min(search.low[cell]);
+                }
+            }
+        }
+        for index in 0..len {
+            if search.required[index] && search.reached(index) {
+                search.require(index);
+            }
+        }
+        search
+    }
+
+    fn reached(&self, index: usize) -> bool {
+        self.discovered[index] != NONE
+    }
+
+    /// Marks a reached cell as required and adds the cells that separate it
+    /// from the root to the cuts. Stops at the first cell whose subtree held
+    /// a required cell before, as the cuts above it are known already.
+    fn require(&mut self, index: usize) {
+        self.required[index] = true;
+        let mut cell = index;
+        while !self.holds_required[cell] {
+            self.holds_required[cell] = true;
+            let parent = self.parent[cell];
+            if parent == NONE {
+                break;
+            }
+            // Removing `parent` would cut this subtree, with a required cell
+            // in it, off from the root, which is required as well.
+            if Some(parent) != self.root && self.low[cell] >= self.discovered[parent] {
+                self.cuts.insert(parent);
+            }
+            cell = parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Cell;
+    use crate::tileset::Extraction;
+    use image::{Rgb, RgbImage};
+    use rand::seq::IndexedRandom;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    /// p0 is walkable, p1 is a wall.
+    const WALK: usize = 0;
+    const WALL: usize = 1;
+
+    fn path(endpoints: &[(usize, usize)], size: (usize, usize)) -> PathConstraint {
+        let colors = [Rgb([255, 255, 255]), Rgb([0, 0, 0])];
+        let image = RgbImage::from_fn(2, 1, |x, _| colors[x as usize]);
+        let tileset = TileSet::<1, 1>::new(&image, Extraction::default()).unwrap();
+        PathConstraint::new(&[Target::Pattern(WALK)], endpoints, &tileset, size).unwrap()
+    }
+
+    fn cells(options: &[&[usize]]) -> Vec<Cell> {
+        options
+            .iter()
+            .map(|options| Cell::new(options.iter().copied().collect()))
+            .collect()
+    }
+
+    /// Calls `hook` on `cells` and returns the bans as `(cell, patterns)`.
+    fn run(
+        cells: &[Cell],
+        (width, periodic): (usize, bool),
+        changed: &[usize],
+        hook: impl FnOnce(&mut Context) -> Result<()>,
+    ) -> Result<Vec<(usize, Vec<usize>)>> {
+        let changed: BitSet = changed.iter().copied().collect();
+        let size = (width, cells.len() / width);
+        let mut context = Context::new(cells, size, periodic, &changed);
+        hook(&mut context)?;
+        Ok(context
+            .into_bans()
+            .into_iter()
+            .map(|(index, banned)| (index, banned.iter().collect()))
+            .collect())
+    }
+
+    #[test]
+    fn forces_cut_cells_to_be_walkable() {
+        // Walkable, undecided, walkable: the middle cell joins the others.
+        let row = cells(&[&[WALK], &[WALK, WALL], &[WALK], &[WALK, WALL]]);
+        let mut path = path(&[], (4, 1));
+        let bans = run(&row, (4, false), &[], |context| path.init(context)).unwrap();
+        assert_eq!(bans, [(1, vec![WALL])]);
+    }
+
+    #[test]
+    fn endpoints_that_cannot_be_joined_contradict() {
+        let row = cells(&[&[WALK, WALL], &[WALL], &[WALK, WALL]]);
+        let mut path = path(&[(0, 0), (2, 0)], (3, 1));
+        let result = run(&row, (3, false), &[], |context| path.init(context));
+        assert!(matches!(result, Err(Error::Contradiction { index: 2 })));
+
+        // Around the edge of a periodic row they are joined.
+        let mut path = self::path(&[(0, 0), (2, 0)], (3, 1));
+        let bans = run(&row, (3, true), &[], |context| path.init(context)).unwrap();
+        assert_eq!(bans, [(0, vec![WALL]), (2, vec![WALL])]);
+    }
+
+    #[test]
+    fn periodic_width_two_grids() {
+        // Cells 2k are the left column, 2k + 1 the right one. On a periodic
+        // grid of width 2, cells of a row neighbor each other twice.
+        let grid = cells(&[&[WALK], &[WALK], &[WALK, WALL], &[WALL], &[WALK], &[WALL]]);
+        let mut path = path(&[], (2, 3));
+        // 1 only reaches the others through 0, while 2 is bypassed by the
+        // rows wrapping around from 4 to 0.
+        let bans = run(&grid, (2, true), &[], |context| path.init(context)).unwrap();
+        assert_eq!(bans, []);
+        let grid = cells(&[
+            &[WALK, WALL],
+            &[WALK],
+            &[WALK, WALL],
+            &[WALL],
+            &[WALK],
+            &[WALL],
+        ]);
+        let bans = run(&grid, (2, true), &[], |context| path.init(context)).unwrap();
+        assert_eq!(bans, [(0, vec![WALL])]);
+        // Without wrapping around, 2 is needed as well.
+        let mut path = self::path(&[], (2, 3));
+        let bans = run(&grid, (2, false), &[], |context| path.init(context)).unwrap();
+        assert_eq!(bans, [(0, vec![WALL]), (2, vec![WALL])]);
+    }
+
+    #[test]
+    fn updates_match_a_fresh_search() {
+        let (width, height) = (6, 5);
+        for seed in 0..60 {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let periodic = seed % 2 == 0;
+            let endpoints: &[(usize, usize)] = if seed % 3 == 0 {
+                &[(0, 0), (5, 4)]
+            } else {
+                &[]
+            };
+            let mut grid = cells(&vec![&[WALK, WALL][..]; width * height]);
+            let mut path = path(endpoints, (width, height));
+            let shape = (width, periodic);
+            let mut bans = run(&grid, shape, &[], |context| path.init(context)).unwrap();
+            loop {
+                // Apply the bans of the last hook, like the grid does, along
+                // with a random observation.
+                let mut changed: Vec<usize> = bans.iter().map(|&(index, _)| index).collect();
+                for (index, banned) in &bans {
+                    for option in banned {
+                        grid[*index].options.remove(*option);
+                    }
+                }
+                let undecided: Vec<usize> = (0..grid.len())
+                    .filter(|&index| grid[index].options.len() > 1)
+                    .collect();
+                let Some(&index) = undecided.choose(&mut rng) else {
+                    break;
+                };
+                grid[index].options.remove(rng.random_range(0..2));
+                changed.push(index);
+
+                let updated = run(&grid, shape, &changed, |context| path.propagated(context));
+                let mut fresh = self::path(endpoints, (width, height));
+                let searched = run(&grid, shape, &[], |context| fresh.init(context));
+                match (updated, searched) {
+                    (Ok(updated), Ok(searched)) => {
+                        assert_eq!(updated, searched, "seed {}", seed);
+                        bans = updated;
+                    }
+                    (Err(Error::Contradiction { .. }), Err(Error::Contradiction { .. })) => break,
+                    (updated, searched) => panic!(
+                        "seed {}: update gave {:?}, a fresh search {:?}",
+                        seed,
+                        updated.map(|_| ()),
+                        searched.map(|_| ())
+                    ),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::error::{Error, Result};
+use crate::tileset::TileSet;
+use image::Rgb;
+use image::imageops::{self, FilterType};
+use std::path::Path;
+
+/// Smallest weight multiplier of a density map. Patterns a map would rule
+/// out stay possible, just very unlikely, so that only the adjacency rules
+/// decide what fits.
+const MIN_WEIGHT: f64 = 1e-6;
+
+/// Default weight multiplier of patterns whose color matches the density
+/// image exactly.
+pub(crate) const DEFAULT_STRENGTH: f64 = 8.0;
+
+/// Scales the weights of the patterns by position, e.g. to get more forest
+/// near the top and more water near the center. Applies to the choice of a
+/// pattern when a cell is observed and to the entropy that decides which
+/// cell is observed next.
+pub(crate) struct Density {
+    weight: Box<dyn Fn(usize, usize, usize) -> f64>,
+}
+
+impl Density {
+    /// Weights pattern `tile` in the cell at `x`, `y` by `weight(x, y, tile)`.
+    pub(crate) fn from_fn(weight: impl Fn(usize, usize, usize) -> f64 + 'static) -> Self {
+        Self {
+            weight: Box::new(weight),
+        }
+    }
+
+    /// Stretches the image at `path` over a grid of `width` x `height` cells
+    /// and weights every pattern by how close its color is to the pixel of
+    /// the cell, from 1 for the opposite color up to `strength` for the same
+    /// color.
+    pub(crate) fn from_image<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        path: &Path,
+        tileset: &TileSet<TILE_WIDTH, TILE_HEIGHT>,
+        (width, height): (usize, usize),
+        strength: f64,
+    ) -> Result<Self>
+    where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        let image = image::open(path)
+            .map_err(|e| Error::image(path, e))?
+            .into_rgb8();
+        let image = imageops::resize(&image, width as u32, height as u32, FilterType::Triangle);
+        // Patterns share few colors, so the multipliers are computed once per
+        // cell and color instead of on every lookup.
+        let mut palette: Vec<Rgb<u8>> = Vec::new();
+        let mut tile_colors = Vec::with_capacity(tileset.len());
+        for tile in 0..tileset.len() {
+            let color = tileset.get_tile(tile).get_rgb();
+            let index = match palette.iter().position(|&known| known == color) {
+                Some(index) => index,
+                None => {
+                    palette.push(color);
+                    palette.len() - 1
+                }
+            };
+            tile_colors.push(index);
+        }
+        let mut weights = Vec::with_capacity(width * height * palette.len());
+        for pixel in image.pixels() {
+            for &color in &palette {
+                weights.push(strength.powf(1.0 - distance(*pixel, color)));
+            }
+        }
+        Ok(Self::from_fn(move |x, y, tile| {
+            weights[(x + y * width) * palette.len() + tile_colors[tile]]
+        }))
+    }
+
+    /// Weight multiplier of pattern `tile` in the cell at `x`, `y`.
+    pub(crate) fn weight(&self, x: usize, y: usize, tile: usize) -> f64 {
+        (self.weight)(x, y, tile).max(MIN_WEIGHT)
+    }
+}
+
+/// Euclidean distance of two colors, normalized to `0..=1`.
+fn distance(Rgb(a): Rgb<u8>, Rgb(b): Rgb<u8>) -> f64 {
+    let squared: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(&a, b)| (a as f64 - b as f64).powi(2))
+        .sum();
+    (squared / (3.0 * 255.0 * 255.0)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Grid;
+    use crate::tileset::Extraction;
+    use image::RgbImage;
+
+    fn grid() -> Grid<1, 1> {
+        let colors = [Rgb([0, 0, 0]), Rgb([255, 255, 255])];
+        let sample = RgbImage::from_fn(4, 4, |x, y| colors[((x + y) % 2) as usize]);
+        let tileset = TileSet::new(&sample, Extraction::default()).unwrap();
+        Grid::with_seed(tileset, 4, 4, 1).unwrap()
+    }
+
+    #[test]
+    fn zero_weights_keep_patterns_possible() {
+        let mut grid = grid();
+        grid.set_density(Density::from_fn(|x, _, tile| -(((x + tile) % 2) as f64)));
+        grid.run_to_completion().unwrap();
+    }
+
+    #[test]
+    fn infinite_weights_are_not_contradictions() {
+        let mut grid = grid();
+        grid.set_density(Density::from_fn(|_, _, _| f64::INFINITY));
+        assert!(matches!(
+            grid.collapse_step(),
+            Err(Error::InvalidWeight { .. })
+        ));
+        assert!(!grid.has_contradiction());
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong while learning tilesets, generating and
+/// showing grids.
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// The sample has fewer pixels than one pattern in some direction.
+    SampleTooSmall {
+        width: usize,
+        height: usize,
+        tile_width: usize,
+        tile_height: usize,
+    },
+    /// The symmetry is outside of 1 to 8, or rotations are requested for
+    /// patterns that are not square.
+    InvalidSymmetry {
+        symmetry: usize,
+    },
+    /// A tileset without patterns, nothing can be generated from it.
+    EmptyTileSet,
+    /// A grid or chunk with a width or height of zero.
+    EmptyGrid,
+    /// A cell was left without any option.
+    Contradiction {
+        index: usize,
+    },
+    /// The weights of the options of a cell are negative, infinite or not a
+    /// number, e.g. because of a broken density map.
+    InvalidWeight {
+        index: usize,
+    },
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A file could not be decoded, e.g. a broken image or cache file.
+    Decode(String),
+    /// Data could not be written to a file, e.g. a tileset with more colors
+    /// than its format can index.
+    Encode(String),
+    /// Invalid command line arguments or batch configuration.
+    Config(String),
+    /// The window system failed.
+    Window(String),
+    /// Some runs of a batch failed, one message per run.
+    Batch(Vec<String>),
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// A decoding error in the file at `path`.
+    pub(crate) fn decode(path: &Path, error: impl fmt::Display) -> Self {
+        Self::Decode(format!("{}: {}", path.display(), error))
+    }
+
+    /// An encoding error writing the file at `path`.
+    pub(crate) fn encode(path: &Path, error: impl fmt::Display) -> Self {
+        Self::Encode(format!("{}: {}", path.display(), error))
+    }
+
+    /// An error reading or writing the image at `path`.
+    pub(crate) fn image(path: &Path, error: image::ImageError) -> Self {
+        match error {
+            image::ImageError::IoError(source) => Self::io(path, source),
+            error => Self::decode(path, error),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SampleTooSmall {
+                width,
+                height,
+                tile_width,
+                tile_height,
+            } => write!(
+                f,
+                "sample of {}x{} pixels is smaller than a {}x{} pattern",
+                width, height, tile_width, tile_height
+            ),
+            Error::InvalidSymmetry { symmetry } => write!(
+                f,
+                "invalid symmetry {}, use 1 to 8 (3 to 8 only for square patterns)",
+                symmetry
+            ),
+            Error::EmptyTileSet => write!(f, "tileset has no patterns"),
+            Error::EmptyGrid => write!(f, "grid has no cells"),
+            Error::Contradiction { index } => write!(f, "no options left for cell {}", index),
+            Error::InvalidWeight { index } => {
+                write!(f, "invalid pattern weights in cell {}", index)
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Decode(message)
+            | Error::Encode(message)
+            | Error::Config(message)
+            | Error::Window(message) => {
+                write!(f, "{}", message)
+            }
+            Error::Batch(failures) => write!(f, "{}", failures.join("\n")),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
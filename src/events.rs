@@ -0,0 +1,97 @@
+use crate::error::{Error, Result};
+use crate::grid::Grid;
+use std::collections::VecDeque;
+
+/// Something that happened inside a `Grid` while generating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Event {
+    /// A cell was observed and collapsed to `tile`.
+    Observed { index: usize, tile: usize },
+    /// Propagation or a constraint removed `count` options from a cell.
+    OptionsRemoved { index: usize, count: usize },
+    /// A cell was left without any option.
+    Contradiction { index: usize },
+    /// The grid was reset to its uncollapsed state.
+    Restart,
+    /// The last uncollapsed cell was observed.
+    Finished,
+}
+
+/// Iterator driving a `Grid` to completion, see `Grid::generate`.
+pub(crate) struct Generation<'a, const TILE_WIDTH: usize, const TILE_HEIGHT: usize>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    grid: &'a mut Grid<TILE_WIDTH, TILE_HEIGHT>,
+    pending: VecDeque<Result<Event>>,
+    restarts_left: usize,
+    record_events: bool,
+    done: bool,
+}
+
+impl<'a, const TILE_WIDTH: usize, const TILE_HEIGHT: usize> Generation<'a, TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    pub(crate) fn new(grid: &'a mut Grid<TILE_WIDTH, TILE_HEIGHT>, max_restarts: usize) -> Self {
+        let record_events = grid.record_events();
+        grid.set_record_events(true);
+        Self {
+            grid,
+            pending: VecDeque::new(),
+            restarts_left: max_restarts,
+            record_events,
+            done: false,
+        }
+    }
+}
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> Iterator
+    for Generation<'_, TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    type Item = Result<Event>;
+
+    /// Yields the events of the next step. The iteration ends with an error
+    /// if a step fails other than by a contradiction, or if a contradiction
+    /// is left once no restarts are.
+    fn next(&mut self) -> Option<Result<Event>> {
+        while self.pending.is_empty() && !self.done {
+            let result = if self.grid.is_collapsed() {
+                self.done = true;
+                Ok(())
+            } else if self.grid.has_contradiction() {
+                if self.restarts_left == 0 {
+                    self.grid.check()
+                } else {
+                    self.restarts_left -= 1;
+                    self.grid.restart();
+                    Ok(())
+                }
+            } else {
+                match self.grid.collapse_step() {
+                    // Shows up as an event, the restart is handled above.
+                    Err(Error::Contradiction { .. }) => Ok(()),
+                    result => result,
+                }
+            };
+            self.pending.extend(self.grid.drain_events().map(Ok));
+            if let Err(e) = result {
+                self.pending.push_back(Err(e));
+                self.done = true;
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> Drop
+    for Generation<'_, TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    fn drop(&mut self) {
+        self.grid.set_record_events(self.record_events);
+    }
+}
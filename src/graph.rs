@@ -0,0 +1,336 @@
+use crate::constraint::Target;
+use crate::error::{Error, Result};
+use crate::grid::{Cell, choose_option, shannon_entropy};
+use crate::tileset::{Direction, SampleSpec, TileSet};
+use bittyset::BitSet;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+/// Attempts before a graph is given up as contradicting.
+const MAX_ATTEMPTS: u64 = 10;
+
+/// Allowed-adjacency relation of one edge label: `relation[option]` holds the
+/// options the node at the other end of the edge may take, just like
+/// `Tile::get_neighbors` does for a grid direction.
+pub(crate) type Relation = Vec<BitSet>;
+
+/// Wave function collapse over an arbitrary graph instead of a regular grid.
+///
+/// Every node carries its own domain and every directed edge carries a label
+/// selecting the [`Relation`] that constrains its target. Observation and
+/// propagation work exactly like in `Grid::collapse_step`, with the same
+/// weighted choice and entropy.
+pub(crate) struct Graph {
+    nodes: Vec<Cell>,
+    edges: Vec<Vec<(usize, usize)>>,
+    relations: Vec<Relation>,
+    /// How likely an observation picks each option, see `Grid::weight`.
+    weights: Vec<f64>,
+    uncollapsed: BitSet<usize>,
+    rng: ChaCha8Rng,
+}
+
+/// A graph file, see `graph.toml`: named nodes, optionally pinned to a
+/// pattern or color, and edges placing one node next to another.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GraphFile {
+    #[serde(rename = "node")]
+    nodes: Vec<NodeSpec>,
+    #[serde(rename = "edge", default)]
+    edges: Vec<EdgeSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeSpec {
+    name: String,
+    /// `p<index>` or `#rrggbb`, like the targets of `--count`.
+    target: Option<String>,
+}
+
+/// Places `to` in `direction` of `from`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EdgeSpec {
+    from: String,
+    to: String,
+    direction: Direction,
+}
+
+impl Graph {
+    /// Creates an empty graph whose observations pick option `i` with a
+    /// probability proportional to `weights[i]` and are fully determined by
+    /// `seed`.
+    pub(crate) fn with_seed(relations: Vec<Relation>, weights: Vec<f64>, seed: u64) -> Self {
+        Self {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            relations,
+            weights,
+            uncollapsed: BitSet::new(),
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Uses the four direction relations of a tileset as edge labels, so that
+    /// `Direction as usize` can be passed as label to [`Graph::connect`], and
+    /// weights the patterns by their frequency like a grid does.
+    pub(crate) fn from_tileset<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        tileset: &TileSet<TILE_WIDTH, TILE_HEIGHT>,
+        seed: u64,
+    ) -> Result<Self>
+    where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        if tileset.len() == 0 {
+            return Err(Error::EmptyTileSet);
+        }
+        let relations = Direction::VALUES
+            .iter()
+            .map(|&direction| {
+                (0..tileset.len())
+                    .map(|index| tileset.get_tile(index).get_neighbors(direction).clone())
+                    .collect()
+            })
+            .collect();
+        let weights = (0..tileset.len())
+            .map(|index| tileset.get_tile(index).get_frequency() as f64)
+            .collect();
+        Ok(Self::with_seed(relations, weights, seed))
+    }
+
+    /// Adds a node with the given domain and returns its index.
+    pub(crate) fn add_node(&mut self, options: BitSet) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Cell::new(options));
+        self.edges.push(Vec::new());
+        self.uncollapsed.insert(index);
+        index
+    }
+
+    /// Adds a directed edge `from -> to` constrained by relation `label`.
+    /// Fails if a node or the relation does not exist.
+    pub(crate) fn add_edge(&mut self, from: usize, to: usize, label: usize) -> Result<()> {
+        if label >= self.relations.len() {
+            return Err(Error::Config(format!(
+                "edge label {} outside of the {} relations",
+                label,
+                self.relations.len()
+            )));
+        }
+        if from >= self.nodes.len() || to >= self.nodes.len() {
+            return Err(Error::Config(format!(
+                "edge {} -> {} outside of the {} nodes",
+                from,
+                to,
+                self.nodes.len()
+            )));
+        }
+        self.edges[from].push((label, to));
+        Ok(())
+    }
+
+    /// Adds an edge in both directions, using `inverse` for the way back.
+    pub(crate) fn connect(
+        &mut self,
+        a: usize,
+        b: usize,
+        label: usize,
+        inverse: usize,
+    ) -> Result<()> {
+        self.add_edge(a, b, label)?;
+        self.add_edge(b, a, inverse)
+    }
+
+    /// Connects `b` to lie in `direction` of `a`, for graphs built with
+    /// [`Graph::from_tileset`].
+    pub(crate) fn connect_direction(
+        &mut self,
+        a: usize,
+        b: usize,
+        direction: Direction,
+    ) -> Result<()> {
+        self.connect(a, b, direction as usize, direction.opposite() as usize)
+    }
+
+    pub(crate) fn get_value(&self, index: usize) -> Option<usize> {
+        self.nodes[index].final_tile
+    }
+
+    pub(crate) fn is_collapsed(&self) -> bool {
+        self.uncollapsed.is_empty()
+    }
+
+    /// Restricts the domain of a node and propagates the change, e.g. to pin
+    /// the entrance of a room graph before generating the rest.
+    pub(crate) fn constrain(&mut self, index: usize, options: &BitSet) -> Result<()> {
+        let node = &mut self.nodes[index];
+        let intersect = BitSet::intersection(&node.options, options);
+        if node.options != intersect {
+            node.options = intersect;
+            if node.options.is_empty() {
+                return Err(Error::Contradiction { index });
+            }
+            self.propagate_options(index)?;
+        }
+        Ok(())
+    }
+
+    /// Collapses every node, failing on the first contradiction.
+    pub(crate) fn run_to_completion(&mut self) -> Result<()> {
+        while !self.is_collapsed() {
+            self.collapse_step()?;
+        }
+        Ok(())
+    }
+
+    /// Observes the node with the lowest entropy and propagates the result.
+    /// Fails if a node is left without options.
+    pub(crate) fn collapse_step(&mut self) -> Result<()> {
+        let Some(min_node_ix) = self.min_node() else {
+            return Ok(());
+        };
+        let options: Vec<(usize, f64)> = self.nodes[min_node_ix]
+            .options
+            .iter()
+            .map(|option| (option, self.weights[option]))
+            .collect();
+        let Some(option) = choose_option(min_node_ix, &options, &mut self.rng)? else {
+            return Err(Error::Contradiction { index: min_node_ix });
+        };
+        let min_node = &mut self.nodes[min_node_ix];
+        min_node.options.clear();
+        min_node.options.insert(option);
+        min_node.final_tile = Some(option);
+        self.uncollapsed.remove(min_node_ix);
+        self.propagate_options(min_node_ix)
+    }
+
+    fn propagate_options(&mut self, index: usize) -> Result<()> {
+        let mut to_update = VecDeque::new();
+        to_update.push_back(index);
+        while let Some(node_ix) = to_update.pop_front() {
+            for &(label, neighbor_ix) in &self.edges[node_ix] {
+                let relation = &self.relations[label];
+                let mut allowed = BitSet::new();
+                for option in self.nodes[node_ix].options.iter() {
+                    allowed.union_with(&relation[option]);
+                }
+                let neighbor = &mut self.nodes[neighbor_ix];
+                if neighbor.final_tile.is_some() {
+                    continue;
+                }
+                let intersect = BitSet::intersection(&allowed, &neighbor.options);
+                if neighbor.options != intersect {
+                    neighbor.options = intersect;
+                    if neighbor.options.is_empty() {
+                        return Err(Error::Contradiction { index: neighbor_ix });
+                    }
+                    if !to_update.contains(&neighbor_ix) {
+                        to_update.push_back(neighbor_ix);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The uncollapsed node with the lowest entropy. Graphs are small, so
+    /// unlike `Grid::min_cell` this scans all of them.
+    fn min_node(&self) -> Option<usize> {
+        let entropy = |index: usize| {
+            let options = self.nodes[index].options.iter();
+            shannon_entropy(options.map(|option| self.weights[option]))
+        };
+        self.uncollapsed
+            .iter()
+            .map(|index| (entropy(index), index))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, index)| index)
+    }
+}
+
+/// Generates the graph file `config` with the patterns learned from
+/// `samples` and prints the pattern and color chosen for every node.
+/// Contradicting attempts are retried with the following seeds.
+pub(crate) fn run<const N: usize>(
+    config: &Path,
+    samples: &[SampleSpec],
+    symmetry: usize,
+    seed: Option<u64>,
+    cache_dir: &Path,
+) -> Result<()>
+where
+    [(); N * N]:,
+{
+    let tileset = &TileSet::<N, N>::from_samples_cached(samples, symmetry, cache_dir)?;
+    let seed = seed.unwrap_or_else(rand::random);
+    let text = fs::read_to_string(config).map_err(|e| Error::io(config, e))?;
+    let file: GraphFile = toml::from_str(&text).map_err(|e| Error::decode(config, e))?;
+    let mut indices = HashMap::new();
+    for (index, node) in file.nodes.iter().enumerate() {
+        if indices.insert(node.name.as_str(), index).is_some() {
+            return Err(Error::Config(format!(
+                "{}: node {} is defined twice",
+                config.display(),
+                node.name
+            )));
+        }
+    }
+    let index = |name: &str| {
+        indices
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::Config(format!("{}: unknown node {}", config.display(), name)))
+    };
+
+    for attempt in 0..MAX_ATTEMPTS {
+        let mut graph = Graph::from_tileset(tileset, seed.wrapping_add(attempt))?;
+        let all_options: BitSet = (0..tileset.len()).collect();
+        for _ in &file.nodes {
+            graph.add_node(all_options.clone());
+        }
+        for edge in &file.edges {
+            graph.connect_direction(index(&edge.from)?, index(&edge.to)?, edge.direction)?;
+        }
+        let mut result = Ok(());
+        for (index, node) in file.nodes.iter().enumerate() {
+            if let Some(target) = &node.target {
+                let target: Target = target.parse().map_err(Error::Config)?;
+                result = graph.constrain(index, &target.tiles(tileset)?);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        match result.and_then(|()| graph.run_to_completion()) {
+            Ok(()) => {
+                for (index, node) in file.nodes.iter().enumerate() {
+                    let tile = graph
+                        .get_value(index)
+                        .expect("collapsed graphs have values");
+                    let color = Target::Color(tileset.get_tile(tile).get_rgb());
+                    println!("{}: {} {}", node.name, Target::Pattern(tile), color);
+                }
+                return Ok(());
+            }
+            Err(Error::Contradiction { index }) => {
+                eprintln!(
+                    "attempt {}: no options left for node {}",
+                    attempt + 1,
+                    file.nodes[index].name
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::Config(format!(
+        "{}: every attempt contradicted",
+        config.display()
+    )))
+}
@@ -1,230 +1,788 @@
-use crate::tileset::{Direction, TileSet};
+mod heap;
+mod serialize;
+
+use crate::constraint::{Constraint, Context};
+use crate::density::Density;
+use crate::error::{Error, Result};
+use crate::events::{Event, Generation};
+use crate::hierarchy::{Guide, GuideMode};
+use crate::render::{Color, Rect, Renderer};
+use crate::stats::Stats;
+use crate::tileset::{Direction, Neighborhood, Overlaps, TileSet};
 use bittyset::BitSet;
+use heap::EntropyHeap;
+use image::{Rgb, RgbImage};
+use rand::distr::weighted::Error as WeightError;
 use rand::seq::IndexedRandom;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{Canvas, TextureCreator};
-use sdl2::ttf::Font;
-use sdl2::video::{Window, WindowContext};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use std::collections::VecDeque;
+use std::time::Instant;
 
-// trait FromVec<T>
-// where
-//     T: Into<usize>,
-// {
-//     fn from_vec(v: Vec<T>) -> BitSet<usize>;
-// }
-
-// impl<T: Into<usize>> FromVec<T> for BitSet<usize> {
-//     fn from_vec(v: Vec<T>) -> BitSet<usize> {
-//         let mut bitset: BitSet<usize> = BitSet::new();
-//         v.into_iter().for_each(|e| {
-//             bitset.insert(e.into());
-//         });
-//         bitset
-//     }
-// }
-
-pub(crate) struct Grid<
-    const TILE_WIDTH: usize,
-    const TILE_HEIGHT: usize,
-    const WIDTH: usize,
-    const HEIGHT: usize,
-> where
+pub(crate) struct Grid<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>
+where
     [(); TILE_WIDTH * TILE_HEIGHT]:,
-    [(); WIDTH * HEIGHT]:,
 {
     pub(crate) tileset: TileSet<TILE_WIDTH, TILE_HEIGHT>,
-    grid: [Cell; WIDTH * HEIGHT],
+    width: usize,
+    height: usize,
+    grid: Vec<Cell>,
     uncollapsed: BitSet<usize>,
+    rng: ChaCha8Rng,
+    periodic: bool,
+    /// The first cell left without options, generation stops there.
+    contradiction: Option<usize>,
+    record_events: bool,
+    events: VecDeque<Event>,
+    stats: Stats,
+    /// Cells whose options changed since the start of the last step.
+    changed: BitSet,
+    /// Coarse layout the generation follows, see `Grid::set_guide`.
+    guide: Option<Guide>,
+    /// Position dependent pattern weights, see `Grid::set_density`.
+    density: Option<Density>,
+    /// Uncollapsed cells by `Grid::entropy`, to be updated whenever the
+    /// options or weights of a cell change.
+    entropies: EntropyHeap,
+    /// Rules of [`Neighborhood::Full`], propagation only follows the
+    /// adjacency of the tileset without them.
+    overlaps: Option<Overlaps>,
+    /// Rules on top of the adjacency rules, see `Grid::add_constraint`.
+    constraints: Vec<Box<dyn Constraint>>,
+}
+
+/// The hooks of a [`Constraint`].
+#[derive(Clone, Copy)]
+enum Hook {
+    Init,
+    Observed { index: usize, tile: usize },
+    Propagated,
+}
+
+/// What `Grid::draw` shows on top of the cells.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Overlay {
+    /// Colors uncollapsed cells by their entropy, normalized to the tileset.
+    pub(crate) entropy: bool,
+    /// Outlines the cells changed by the last step.
+    pub(crate) wavefront: bool,
 }
 
-impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> Grid<TILE_WIDTH, TILE_HEIGHT, 0, 0>
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> Grid<TILE_WIDTH, TILE_HEIGHT>
 where
     [(); TILE_WIDTH * TILE_HEIGHT]:,
 {
-    pub(crate) fn new<const WIDTH: usize, const HEIGHT: usize>(
+    /// Creates a grid whose observations are fully determined by `seed`.
+    pub(crate) fn with_seed(
         tileset: TileSet<TILE_WIDTH, TILE_HEIGHT>,
-    ) -> Grid<TILE_WIDTH, TILE_HEIGHT, WIDTH, HEIGHT>
-    where
-        [(); WIDTH * HEIGHT]:,
-    {
+        width: usize,
+        height: usize,
+        seed: u64,
+    ) -> Result<Grid<TILE_WIDTH, TILE_HEIGHT>> {
+        if tileset.len() == 0 {
+            return Err(Error::EmptyTileSet);
+        }
+        if width == 0 || height == 0 {
+            return Err(Error::EmptyGrid);
+        }
         let all_options: BitSet = (0..tileset.len()).collect();
-        Grid {
+        Ok(Grid {
             tileset,
-            grid: std::array::from_fn(|_| Cell::new(all_options.clone())),
-            uncollapsed: (0..(WIDTH * HEIGHT)).collect(),
+            width,
+            height,
+            grid: vec![Cell::new(all_options); width * height],
+            uncollapsed: (0..(width * height)).collect(),
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            periodic: true,
+            contradiction: None,
+            record_events: false,
+            events: VecDeque::new(),
+            stats: Stats::default(),
+            changed: BitSet::new(),
+            guide: None,
+            density: None,
+            entropies: EntropyHeap::new(width * height),
+            overlaps: None,
+            constraints: Vec::new(),
+        })
+    }
+
+    #[inline(always)]
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline(always)]
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+    /// Puts every cell back into its uncollapsed state and reseeds the RNG.
+    pub(crate) fn reset(&mut self, seed: u64) {
+        let all_options: BitSet = (0..self.tileset.len()).collect();
+        for cell in self.grid.iter_mut() {
+            *cell = Cell::new(all_options.clone());
         }
+        self.uncollapsed = (0..(self.width * self.height)).collect();
+        self.entropies.invalidate_all();
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.contradiction = None;
+        self.changed.clear();
+        self.emit(Event::Restart);
+        // Constraints fail with contradictions only, which `check` reports.
+        let _ = self.init_constraints();
+        // A contradiction of the pinned cells shows up in the next step.
+        let _ = self.apply_guide();
     }
-}
 
-impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize, const WIDTH: usize, const HEIGHT: usize>
-    Grid<TILE_WIDTH, TILE_HEIGHT, WIDTH, HEIGHT>
-where
-    [(); TILE_WIDTH * TILE_HEIGHT]:,
-    [(); WIDTH * HEIGHT]:,
-{
-    pub(crate) fn collapse_step(&mut self) {
-        let Some(min_cell_ix) = self.min_cell() else {
-            return;
+    /// Whether opposite edges of the grid are considered adjacent.
+    pub(crate) fn set_periodic(&mut self, periodic: bool) {
+        self.periodic = periodic;
+    }
+
+    /// Which cells a change is propagated to. [`Neighborhood::Full`]
+    /// computes the rules for all overlapping offsets first.
+    pub(crate) fn set_neighborhood(&mut self, neighborhood: Neighborhood) {
+        self.overlaps = match neighborhood {
+            Neighborhood::Cardinal => None,
+            Neighborhood::Full => Some(self.tileset.overlaps()),
         };
-        let min_cell = &mut self.grid[min_cell_ix];
-        // if min_cell.final_tile.is_some() {
-        //     println!("DBG");
-        // }
-        let options: Vec<usize> = min_cell.options.iter().collect();
-        // let Some(&option) = options.first() else {
-        //     println!("ERROR: no options for cell {}", min_cell_ix);
-        //     return;
-        // };
-        let Some(&option) = options.choose(&mut rand::rng()) else {
-            println!("ERROR: no options for cell {}", min_cell_ix);
-            return;
+    }
+
+    pub(crate) fn is_collapsed(&self) -> bool {
+        self.uncollapsed.is_empty()
+    }
+
+    pub(crate) fn has_contradiction(&self) -> bool {
+        self.contradiction.is_some()
+    }
+
+    /// Fails with the contradiction the grid ran into, if any.
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.contradiction {
+            Some(index) => Err(Error::Contradiction { index }),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn get_final_tile(&self, index: usize) -> Option<usize> {
+        self.grid[index].final_tile
+    }
+
+    /// Restricts the options of a cell, e.g. to match already generated
+    /// content next to the grid, and propagates the change.
+    pub(crate) fn constrain(&mut self, index: usize, options: &BitSet) -> Result<()> {
+        if self.remove_options(index, options) {
+            self.propagate_options(index);
+        }
+        self.enforce_constraints()?;
+        self.check()
+    }
+
+    /// Keeps only `options` of a cell. Returns whether the cell lost options
+    /// and has some left, i.e. whether the change needs to be propagated.
+    fn remove_options(&mut self, index: usize, options: &BitSet) -> bool {
+        let cell = &mut self.grid[index];
+        let intersect = BitSet::intersection(&cell.options, options);
+        if cell.options == intersect {
+            return false;
+        }
+        let count = cell.options.len() - intersect.len();
+        cell.options = intersect;
+        let emptied = cell.options.is_empty();
+        self.changed.insert(index);
+        self.entropies.invalidate(index);
+        self.stats.options_removed += count as u64;
+        self.emit(Event::OptionsRemoved { index, count });
+        if emptied {
+            self.mark_contradiction(index);
+        }
+        !emptied
+    }
+
+    /// Adds a rule the generation has to follow from now on, including after
+    /// resets, and applies it to the current cells.
+    pub(crate) fn add_constraint(&mut self, constraint: Box<dyn Constraint>) -> Result<()> {
+        self.constraints.push(constraint);
+        self.init_constraints()?;
+        self.check()
+    }
+
+    /// Lets every constraint rebuild its state from the cells, then enforces
+    /// them.
+    fn init_constraints(&mut self) -> Result<()> {
+        self.run_constraints(Hook::Init)?;
+        self.enforce_constraints()
+    }
+
+    /// Tells the constraints about an observation and enforces them.
+    fn observed(&mut self, index: usize, tile: usize) -> Result<()> {
+        self.run_constraints(Hook::Observed { index, tile })?;
+        self.enforce_constraints()
+    }
+
+    /// Calls the constraints after propagation until none of them bans
+    /// options anymore, since bans by one may affect the others.
+    fn enforce_constraints(&mut self) -> Result<()> {
+        while self.contradiction.is_none() && self.run_constraints(Hook::Propagated)? {}
+        Ok(())
+    }
+
+    /// Calls `hook` of every constraint, then removes the options they
+    /// banned and propagates the removal. Contradictions signaled by a
+    /// constraint are marked like any other. Returns whether options were
+    /// removed.
+    fn run_constraints(&mut self, hook: Hook) -> Result<bool> {
+        if self.constraints.is_empty() || self.contradiction.is_some() {
+            return Ok(false);
+        }
+        let mut constraints = std::mem::take(&mut self.constraints);
+        let mut context = Context::new(
+            &self.grid,
+            (self.width, self.height),
+            self.periodic,
+            &self.changed,
+        );
+        let mut result = Ok(());
+        for constraint in &mut constraints {
+            result = match hook {
+                Hook::Init => constraint.init(&mut context),
+                Hook::Observed { index, tile } => constraint.observed(&mut context, index, tile),
+                Hook::Propagated => constraint.propagated(&mut context),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        let bans = context.into_bans();
+        self.constraints = constraints;
+        match result {
+            Err(Error::Contradiction { index }) => {
+                self.mark_contradiction(index);
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+            Ok(()) => {}
+        }
+        let mut to_update = VecDeque::new();
+        for &(index, ref banned) in &bans {
+            let options = BitSet::difference(&self.grid[index].options, banned);
+            if self.remove_options(index, &options) && !to_update.contains(&index) {
+                to_update.push_back(index);
+            }
+        }
+        self.propagate_queue(to_update);
+        Ok(!bans.is_empty())
+    }
+
+    /// Collapses a cell to `tile`, e.g. when painting in the viewer. A cell
+    /// that was collapsed to a different tile before is erased first.
+    pub(crate) fn set_tile(&mut self, index: usize, tile: usize) -> Result<()> {
+        if self.grid[index]
+            .final_tile
+            .is_some_and(|final_tile| final_tile != tile)
+        {
+            self.erase([index]);
+        }
+        let only_tile: BitSet = [tile].into_iter().collect();
+        let result = self.constrain(index, &only_tile);
+        let cell = &mut self.grid[index];
+        if !cell.options.is_empty() && cell.final_tile.is_none() {
+            cell.final_tile = Some(tile);
+            self.uncollapsed.remove(index);
+            self.entropies.invalidate(index);
+            self.emit(Event::Observed { index, tile });
+            return result.and_then(|()| {
+                self.observed(index, tile)?;
+                self.check()
+            });
+        }
+        result
+    }
+
+    /// Restricts a cell to the tiles of the given color.
+    pub(crate) fn constrain_color(&mut self, index: usize, color: Rgb<u8>) -> Result<()> {
+        let options = self.tileset.tiles_with_color(color);
+        self.constrain(index, &options)
+    }
+
+    /// Puts the given cells back into their uncollapsed state. Since their
+    /// old options constrained their surroundings, the domains of all other
+    /// uncollapsed cells are recomputed from the collapsed ones.
+    pub(crate) fn erase(&mut self, indices: impl IntoIterator<Item = usize>) {
+        self.changed.clear();
+        for index in indices {
+            self.grid[index].final_tile = None;
+            self.changed.insert(index);
+        }
+        let all_options: BitSet = (0..self.tileset.len()).collect();
+        let mut collapsed = VecDeque::new();
+        for (index, cell) in self.grid.iter_mut().enumerate() {
+            match cell.final_tile {
+                Some(tile) => {
+                    cell.options = [tile].into_iter().collect();
+                    collapsed.push_back(index);
+                }
+                None => {
+                    cell.options = all_options.clone();
+                    self.uncollapsed.insert(index);
+                }
+            }
+        }
+        self.contradiction = None;
+        self.entropies.invalidate_all();
+        self.propagate_queue(collapsed);
+        let _ = self.init_constraints();
+        let _ = self.apply_guide();
+    }
+
+    /// Pins the ground pattern of the tileset to the bottom row of the grid
+    /// and bans it everywhere else.
+    pub(crate) fn apply_ground(&mut self) -> Result<()> {
+        let ground = self.tileset.ground();
+        let only_ground: BitSet = [ground].into_iter().collect();
+        let without_ground: BitSet = (0..self.tileset.len())
+            .filter(|&index| index != ground)
+            .collect();
+        for index in 0..self.width * self.height {
+            if index / self.width == self.height - 1 {
+                self.constrain(index, &only_ground)?;
+            } else {
+                self.constrain(index, &without_ground)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the generation follow `guide`, which must have a color for
+    /// every cell. Hard guides pin their cells right away and again after
+    /// every reset.
+    pub(crate) fn set_guide(&mut self, guide: Guide) -> Result<()> {
+        if guide.colors.len() != self.width * self.height {
+            return Err(Error::Config(format!(
+                "guide has {} colors for {} cells",
+                guide.colors.len(),
+                self.width * self.height
+            )));
+        }
+        self.guide = Some(guide);
+        self.entropies.invalidate_all();
+        self.apply_guide()
+    }
+
+    fn apply_guide(&mut self) -> Result<()> {
+        let Some(guide) = self.guide.take() else {
+            return Ok(());
         };
-        // let &option = options.choose(&mut rand::rng()).unwrap();
+        let mut result = Ok(());
+        if guide.mode == GuideMode::Hard {
+            for &index in &guide.pinned {
+                result = self.constrain_color(index, guide.colors[index]);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        self.guide = Some(guide);
+        result
+    }
+
+    /// Scales the pattern weights of every cell by `density` from now on.
+    pub(crate) fn set_density(&mut self, density: Density) {
+        self.density = Some(density);
+        self.entropies.invalidate_all();
+    }
+
+    /// Weight of pattern `tile` in cell `index`: its frequency in the sample,
+    /// scaled by the guide and the density map.
+    fn weight(&self, index: usize, tile: usize) -> f64 {
+        let pattern = self.tileset.get_tile(tile);
+        let mut weight = pattern.get_frequency() as f64;
+        if let Some(guide) = &self.guide {
+            weight *= guide.weight(index, pattern.get_rgb());
+        }
+        if let Some(density) = &self.density {
+            weight *= density.weight(index % self.width, index / self.width, tile);
+        }
+        weight
+    }
+
+    /// Starts over from an empty grid, seeded from the current RNG so that
+    /// runs stay reproducible.
+    pub(crate) fn restart(&mut self) {
+        let seed = self.rng.random();
+        self.restart_with_seed(seed);
+    }
+
+    /// Starts over from an empty grid like [`Grid::reset`], but counts as a
+    /// restart in the stats.
+    pub(crate) fn restart_with_seed(&mut self, seed: u64) {
+        self.stats.restarts += 1;
+        self.reset(seed);
+    }
+
+    /// Enables or disables buffering of [`Event`]s for [`Grid::drain_events`].
+    pub(crate) fn set_record_events(&mut self, record_events: bool) {
+        self.record_events = record_events;
+        if !record_events {
+            self.events.clear();
+        }
+    }
+
+    pub(crate) fn record_events(&self) -> bool {
+        self.record_events
+    }
+
+    /// Takes all events recorded since the last call.
+    pub(crate) fn drain_events(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Runs the generation to completion, yielding every event on the way and
+    /// restarting at most `max_restarts` times after a contradiction.
+    pub(crate) fn generate(
+        &mut self,
+        max_restarts: usize,
+    ) -> Generation<'_, TILE_WIDTH, TILE_HEIGHT> {
+        Generation::new(self, max_restarts)
+    }
+
+    /// Counters and timings collected since the grid was created.
+    pub(crate) fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    #[inline(always)]
+    fn emit(&mut self, event: Event) {
+        if self.record_events {
+            self.events.push_back(event);
+        }
+    }
+
+    fn mark_contradiction(&mut self, index: usize) {
+        if self.contradiction.is_none() {
+            self.stats.contradictions += 1;
+            self.contradiction = Some(index);
+        }
+        self.emit(Event::Contradiction { index });
+    }
+
+    /// Observes the cell with the lowest entropy and propagates the result.
+    /// Fails once a cell is left without options; the grid stays in that
+    /// state until it is reset or the offending cells are erased.
+    pub(crate) fn collapse_step(&mut self) -> Result<()> {
+        self.check()?;
+        self.changed.clear();
+        let start = Instant::now();
+        let min_cell_ix = self.min_cell();
+        self.stats.min_cell_time += start.elapsed();
+        let Some(min_cell_ix) = min_cell_ix else {
+            return Ok(());
+        };
+        let options: Vec<(usize, f64)> = self.grid[min_cell_ix]
+            .options
+            .iter()
+            .map(|option| (option, self.weight(min_cell_ix, option)))
+            .collect();
+        let Some(option) = choose_option(min_cell_ix, &options, &mut self.rng)? else {
+            self.mark_contradiction(min_cell_ix);
+            return self.check();
+        };
+        let min_cell = &mut self.grid[min_cell_ix];
         min_cell.options.clear();
         min_cell.options.insert(option);
         min_cell.final_tile = Some(option);
         self.uncollapsed.remove(min_cell_ix);
+        self.changed.insert(min_cell_ix);
+        self.entropies.invalidate(min_cell_ix);
+        self.stats.observations += 1;
+        self.emit(Event::Observed {
+            index: min_cell_ix,
+            tile: option,
+        });
+        // Constraints see the observation with its consequences propagated.
         self.propagate_options(min_cell_ix);
+        self.observed(min_cell_ix, option)?;
+        self.check()?;
+        if self.uncollapsed.is_empty() {
+            self.emit(Event::Finished);
+        }
+        Ok(())
+    }
+
+    /// Steps until every cell is collapsed or a contradiction is hit.
+    pub(crate) fn run_to_completion(&mut self) -> Result<()> {
+        while !self.is_collapsed() {
+            self.collapse_step()?;
+        }
+        self.check()
     }
 
     fn propagate_options(&mut self, index: usize) {
-        let mut to_update = VecDeque::new();
-        to_update.push_back(index);
+        self.propagate_queue(VecDeque::from([index]));
+    }
+
+    fn propagate_queue(&mut self, mut to_update: VecDeque<usize>) {
+        let start = Instant::now();
+        self.stats.queue_pushes += to_update.len() as u64;
+        let links = self
+            .overlaps
+            .as_ref()
+            .map_or(Direction::VALUES.len(), |overlaps| overlaps.offsets().len());
         while let Some(cell_ix) = to_update.pop_front() {
-            // println!("to_update.len() = {}", to_update.len());
-            for direction in Direction::VALUES {
-                let neighbor_ix = self.get_neighbor(cell_ix, direction);
-                // println!("### cell_ix = {}, neighbor_ix = {}", cell_ix, neighbor_ix);
-                let [cell, neighbor] = self.grid.get_many_mut([cell_ix, neighbor_ix]).unwrap();
-                if neighbor.options.len() <= 1 {
+            for link in 0..links {
+                let neighbor_ix = match &self.overlaps {
+                    Some(overlaps) => offset(
+                        (self.width, self.height),
+                        self.periodic,
+                        cell_ix,
+                        overlaps.offsets()[link],
+                    ),
+                    None => self.get_neighbor(cell_ix, Direction::VALUES[link]),
+                };
+                // Offsets wrap around onto the cell itself on grids smaller
+                // than a pattern.
+                let Some(neighbor_ix) = neighbor_ix.filter(|&ix| ix != cell_ix) else {
+                    continue;
+                };
+                if self.grid[neighbor_ix].options.len() <= 1 {
                     continue;
                 }
-                // println!("cell.options = {:?}, {}", cell.options, cell.options.len());
-                // println!(
-                // "neighbor.options = {:?}, {}",
-                // neighbor.options,
-                // neighbor.options.len()
-                // );
-                let mut tile_neighbor_options_iter = cell
-                    .options
-                    .iter()
-                    .map(|index| self.tileset.get_tile(index).get_neighbors(direction));
+                let mut tile_neighbor_options_iter =
+                    self.grid[cell_ix]
+                        .options
+                        .iter()
+                        .map(|index| match &self.overlaps {
+                            Some(overlaps) => overlaps.compatible(index, link),
+                            None => self
+                                .tileset
+                                .get_tile(index)
+                                .get_neighbors(Direction::VALUES[link]),
+                        });
                 let Some(mut tile_neighbor_options) = tile_neighbor_options_iter.next().cloned()
                 else {
                     break;
                 };
-                // println!("tile_neighbor_options = {:?}", tile_neighbor_options);
                 tile_neighbor_options =
                     tile_neighbor_options_iter.fold(tile_neighbor_options, |acc, e| acc.union(e));
-                // println!(
-                // "tile_neighbor_options = {:?}, {}",
-                // tile_neighbor_options,
-                // tile_neighbor_options.len()
-                // );
-                // if neighbor_ix == 13 {
-                // println!("DBG");
-                // }
+                let neighbor = &mut self.grid[neighbor_ix];
                 let intersect = BitSet::intersection(&tile_neighbor_options, &neighbor.options);
                 if neighbor.options != intersect {
+                    let count = neighbor.options.len() - intersect.len();
                     neighbor.options = intersect;
-                    // println!("neighbor.options = {:?}", neighbor.options);
+                    let emptied = neighbor.options.is_empty();
+                    self.changed.insert(neighbor_ix);
+                    self.entropies.invalidate(neighbor_ix);
                     if !to_update.contains(&neighbor_ix) {
                         to_update.push_front(neighbor_ix);
+                        self.stats.queue_pushes += 1;
+                    }
+                    self.stats.options_removed += count as u64;
+                    self.emit(Event::OptionsRemoved {
+                        index: neighbor_ix,
+                        count,
+                    });
+                    if emptied {
+                        self.mark_contradiction(neighbor_ix);
                     }
                 }
             }
         }
+        self.stats.propagate_time += start.elapsed();
     }
 
-    fn get_neighbor(&self, index: usize, direction: Direction) -> usize {
-        let mut x = (index % WIDTH) as isize;
-        let mut y = (index / WIDTH) as isize;
-        match direction {
-            Direction::North => y -= 1,
-            Direction::East => x += 1,
-            Direction::South => y += 1,
-            Direction::West => x -= 1,
-        };
-        x = x.rem_euclid(WIDTH as isize);
-        y = y.rem_euclid(HEIGHT as isize);
-        x as usize + y as usize * WIDTH
+    fn get_neighbor(&self, index: usize, direction: Direction) -> Option<usize> {
+        neighbor((self.width, self.height), self.periodic, index, direction)
     }
 
-    fn entropy(&self, index: usize) -> usize {
-        self.grid[index].entropy()
+    /// Shannon entropy of the options of a cell, weighted like an
+    /// observation of the cell would weight them.
+    fn entropy(&self, index: usize) -> f64 {
+        let options = self.grid[index].options.iter();
+        shannon_entropy(options.map(|tile| self.weight(index, tile)))
     }
 
-    fn min_cell(&self) -> Option<usize> {
-        self.uncollapsed
-            .iter()
-            .min_by_key(|&index| self.entropy(index))
+    /// The uncollapsed cell with the lowest entropy. Only the entropies of
+    /// cells that changed since the last call are computed, and each moves
+    /// in the heap in logarithmic time.
+    fn min_cell(&mut self) -> Option<usize> {
+        if self.entropies.is_all_stale() {
+            let entries = self
+                .uncollapsed
+                .iter()
+                .map(|index| (self.entropy(index), index))
+                .collect();
+            self.entropies.rebuild(entries);
+        } else {
+            for index in self.entropies.take_stale() {
+                if self.uncollapsed.contains(index) {
+                    let entropy = self.entropy(index);
+                    self.entropies.set(index, entropy);
+                } else {
+                    self.entropies.remove(index);
+                }
+            }
+        }
+        self.entropies.min()
     }
 
-    pub(crate) fn draw(
-        &self,
-        canvas: &mut Canvas<Window>,
-        texture_creator: &TextureCreator<WindowContext>,
-        font: &Font,
-        scale: u32,
-    ) {
+    pub(crate) fn draw(&self, renderer: &mut dyn Renderer, scale: u32, overlay: Overlay) {
+        let max_entropy = (self.tileset.len() as f32).ln().max(f32::EPSILON);
         for (index, cell) in self.grid.iter().enumerate() {
-            let x = (index % WIDTH) as i32 * scale as i32;
-            let y = (index / WIDTH) as i32 * scale as i32;
+            let x = (index % self.width) as i32 * scale as i32;
+            let y = (index / self.width) as i32 * scale as i32;
             let rect = Rect::new(x, y, scale, scale);
 
             if let Some(tile_i) = cell.final_tile {
                 let tile = self.tileset.get_tile(tile_i);
-                // tile.draw(canvas, x, y, scale);
-                canvas.set_draw_color(tile.get_color());
-                let _ = canvas.fill_rect(rect);
+                renderer.fill_rect(rect, tile.get_color());
+            } else if cell.options.is_empty() {
+                renderer.fill_rect(rect, Color::RED);
+            } else if overlay.entropy {
+                let entropy = self.entropy(index) as f32 / max_entropy;
+                renderer.fill_rect(rect, heat_color(entropy));
             } else {
-                canvas.set_draw_color(Color::MAGENTA);
-                let _ = canvas.draw_rect(rect);
+                renderer.draw_rect(rect, Color::MAGENTA);
                 let text = format!("{}", cell.options.len());
-                Self::write_text(canvas, texture_creator, font, &text, x, y);
+                renderer.draw_text(&text, x, y, Color::WHITE);
+            }
+            if overlay.wavefront && self.changed.contains(index) {
+                renderer.draw_rect(rect, Color::CYAN);
+                let inner = Rect::new(
+                    x + 1,
+                    y + 1,
+                    scale.saturating_sub(2),
+                    scale.saturating_sub(2),
+                );
+                renderer.draw_rect(inner, Color::CYAN);
+            }
+        }
+    }
+
+    /// Renders the grid into an image with `scale` x `scale` pixels per cell:
+    /// collapsed cells get the color of their tile, uncollapsed cells the
+    /// average color of their remaining options.
+    pub(crate) fn render(&self, scale: u32) -> RgbImage {
+        let mut image = RgbImage::new(self.width as u32 * scale, self.height as u32 * scale);
+        for (index, cell) in self.grid.iter().enumerate() {
+            let color = match cell.final_tile {
+                Some(tile_i) => self.tileset.get_tile(tile_i).get_rgb(),
+                None => self.average_color(&cell.options),
+            };
+            let x = (index % self.width) as u32 * scale;
+            let y = (index / self.width) as u32 * scale;
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    image.put_pixel(x + dx, y + dy, color);
+                }
+            }
+        }
+        image
+    }
+
+    fn average_color(&self, options: &BitSet) -> Rgb<u8> {
+        let count = options.len() as u32;
+        if count == 0 {
+            return Rgb([255, 0, 255]);
+        }
+        let mut sum = [0u32; 3];
+        for index in options.iter() {
+            let Rgb(color) = self.tileset.get_tile(index).get_rgb();
+            for (channel, value) in sum.iter_mut().zip(color) {
+                *channel += value as u32;
             }
         }
+        Rgb(sum.map(|channel| (channel / count) as u8))
+    }
+}
+
+/// The cell next to `index` in `direction` on a grid of `width` x `height`
+/// cells, wrapping around if `periodic`.
+pub(crate) fn neighbor(
+    (width, height): (usize, usize),
+    periodic: bool,
+    index: usize,
+    direction: Direction,
+) -> Option<usize> {
+    let delta = match direction {
+        Direction::North => (0, -1),
+        Direction::East => (1, 0),
+        Direction::South => (0, 1),
+        Direction::West => (-1, 0),
+    };
+    offset((width, height), periodic, index, delta)
+}
+
+/// The cell `dx`, `dy` cells away from `index`, see [`neighbor`].
+pub(crate) fn offset(
+    (width, height): (usize, usize),
+    periodic: bool,
+    index: usize,
+    (dx, dy): (isize, isize),
+) -> Option<usize> {
+    let x = (index % width) as isize + dx;
+    let y = (index / width) as isize + dy;
+    if !periodic && (x < 0 || y < 0 || x >= width as isize || y >= height as isize) {
+        return None;
+    }
+    let x = x.rem_euclid(width as isize);
+    let y = y.rem_euclid(height as isize);
+    Some(x as usize + y as usize * width)
+}
+
+/// Maps `t` in `0..=1` from dark blue over red to yellow.
+/// Picks one of the `(option, weight)` pairs of cell `index` with a
+/// probability proportional to its weight, or none if there are no options
+/// or all weights are zero. Fails on weights that are negative or not
+/// finite, or that sum to infinity. Shared by grids and graphs.
+pub(crate) fn choose_option(
+    index: usize,
+    options: &[(usize, f64)],
+    rng: &mut impl Rng,
+) -> Result<Option<usize>> {
+    // `choose_weighted` panics instead of failing on infinite weights, and
+    // the sum is not finite if any weight is NaN.
+    let total: f64 = options.iter().map(|&(_, weight)| weight).sum();
+    if !total.is_finite() || options.iter().any(|&(_, weight)| weight < 0.0) {
+        return Err(Error::InvalidWeight { index });
+    }
+    match options.choose_weighted(rng, |&(_, weight)| weight) {
+        Ok(&(option, _)) => Ok(Some(option)),
+        Err(WeightError::InvalidInput | WeightError::InsufficientNonZero) => Ok(None),
+        Err(_) => Err(Error::InvalidWeight { index }),
     }
+}
 
-    pub(crate) fn write_text(
-        canvas: &mut Canvas<Window>,
-        texture_creator: &TextureCreator<WindowContext>,
-        font: &Font,
-        text: &str,
-        x: i32,
-        y: i32,
-    ) {
-        let surface = font.render(text).blended(Color::WHITE).unwrap();
-        let texture = texture_creator
-            .create_texture_from_surface(&surface)
-            .unwrap();
-        canvas
-            .copy(
-                &texture,
-                None,
-                Some(Rect::new(x, y, surface.width(), surface.height())),
-            )
-            .unwrap();
+/// Shannon entropy of options with the given weights, which need not sum to
+/// one.
+pub(crate) fn shannon_entropy(weights: impl IntoIterator<Item = f64>) -> f64 {
+    let (mut sum, mut sum_log) = (0.0, 0.0);
+    for weight in weights {
+        sum += weight;
+        sum_log += weight * weight.ln();
+    }
+    if sum <= 0.0 {
+        return 0.0;
     }
+    sum.ln() - sum_log / sum
 }
 
-struct Cell {
-    final_tile: Option<usize>,
-    options: BitSet,
+fn heat_color(t: f32) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0) as u8;
+    Color::rgb(channel(2.0 * t), channel(2.0 * t - 1.0), channel(0.5 - t))
+}
+
+#[derive(Clone)]
+pub(crate) struct Cell {
+    pub(crate) final_tile: Option<usize>,
+    pub(crate) options: BitSet,
 }
 
 impl Cell {
-    fn new(options: BitSet) -> Self {
+    pub(crate) fn new(options: BitSet) -> Self {
         Self {
             final_tile: None,
             options,
         }
     }
-
-    #[inline(always)]
-    fn entropy(&self) -> usize {
-        self.options.len()
-    }
 }
@@ -0,0 +1,249 @@
+use std::cmp::Ordering;
+
+/// Marks a cell that is not in the heap.
+const ABSENT: usize = usize::MAX;
+
+/// Binary min-heap of cells keyed by their entropy, with the position of
+/// every cell so that its key can be changed in place. Ties go to the lower
+/// cell index, which makes the minimum the same as a scan in index order.
+///
+/// Cells whose entropy changed are only marked stale; the grid recomputes
+/// them in one go before asking for the minimum, see `Grid::min_cell`.
+pub(crate) struct EntropyHeap {
+    /// `(entropy, cell)` entries in heap order.
+    entries: Vec<(f64, usize)>,
+    /// Position of every cell in `entries`, or [`ABSENT`].
+    positions: Vec<usize>,
+    stale: Vec<usize>,
+    is_stale: Vec<bool>,
+    /// Whether every cell is stale, e.g. after a reset.
+    all_stale: bool,
+}
+
+impl EntropyHeap {
+    /// Creates a heap for `len` cells, all stale.
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(len),
+            positions: vec![ABSENT; len],
+            stale: Vec::new(),
+            is_stale: vec![false; len],
+            all_stale: true,
+        }
+    }
+
+    /// Marks the entropy of a cell as outdated.
+    pub(crate) fn invalidate(&mut self, cell: usize) {
+        if !self.all_stale && !self.is_stale[cell] {
+            self.is_stale[cell] = true;
+            self.stale.push(cell);
+        }
+    }
+
+    /// Marks the entropies of all cells as outdated.
+    pub(crate) fn invalidate_all(&mut self) {
+        self.all_stale = true;
+        for cell in self.stale.drain(..) {
+            self.is_stale[cell] = false;
+        }
+    }
+
+    pub(crate) fn is_all_stale(&self) -> bool {
+        self.all_stale
+    }
+
+    /// The stale cells, which are no longer stale afterwards.
+    pub(crate) fn take_stale(&mut self) -> Vec<usize> {
+        for &cell in &self.stale {
+            self.is_stale[cell] = false;
+        }
+        std::mem::take(&mut self.stale)
+    }
+
+    /// Replaces the whole heap with `entries`, which are no longer stale.
+    pub(crate) fn rebuild(&mut self, entries: Vec<(f64, usize)>) {
+        self.positions.fill(ABSENT);
+        for (position, &(_, cell)) in entries.iter().enumerate() {
+            self.positions[cell] = position;
+        }
+        self.entries = entries;
+        for position in (0..self.entries.len() / 2).rev() {
+            self.sift_down(position);
+        }
+        for cell in self.stale.drain(..) {
+            self.is_stale[cell] = false;
+        }
+        self.all_stale = false;
+    }
+
+    /// Inserts a cell or changes its entropy.
+    pub(crate) fn set(&mut self, cell: usize, entropy: f64) {
+        let position = match self.positions[cell] {
+            ABSENT => {
+                self.entries.push((entropy, cell));
+                self.positions[cell] = self.entries.len() - 1;
+                self.entries.len() - 1
+            }
+            position => {
+                self.entries[position].0 = entropy;
+                position
+            }
+        };
+        self.sift_up(position);
+        self.sift_down(self.positions[cell]);
+    }
+
+    pub(crate) fn remove(&mut self, cell: usize) {
+        let position = self.positions[cell];
+        if position == ABSENT {
+            return;
+        }
+        self.positions[cell] = ABSENT;
+        let last = self
+            .entries
+            .pop()
+            .expect("a cell with a position is in the heap");
+        if position < self.entries.len() {
+            self.entries[position] = last;
+            self.positions[last.1] = position;
+            self.sift_up(position);
+            self.sift_down(self.positions[last.1]);
+        }
+    }
+
+    /// The cell with the lowest entropy.
+    pub(crate) fn min(&self) -> Option<usize> {
+        self.entries.first().map(|&(_, cell)| cell)
+    }
+
+    fn less(&self, a: usize, b: usize) -> bool {
+        let (entropy_a, cell_a) = self.entries[a];
+        let (entropy_b, cell_b) = self.entries[b];
+        entropy_a.total_cmp(&entropy_b).then(cell_a.cmp(&cell_b)) == Ordering::Less
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.entries.swap(a, b);
+        self.positions[self.entries[a].1] = a;
+        self.positions[self.entries[b].1] = b;
+    }
+
+    fn sift_up(&mut self, mut position: usize) {
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            if !self.less(position, parent) {
+                break;
+            }
+            self.swap(position, parent);
+            position = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut position: usize) {
+        loop {
+            let mut smallest = position;
+            for child in [2 * position + 1, 2 * position + 2] {
+                if child < self.entries.len() && self.less(child, smallest) {
+                    smallest = child;
+                }
+            }
+            if smallest == position {
+                break;
+            }
+            self.swap(position, smallest);
+            position = smallest;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    const CELLS: usize = 50;
+
+    /// The cell with the lowest entropy in `entropies`, ties going to the
+    /// lower index.
+    fn scan(entropies: &[Option<f64>]) -> Option<usize> {
+        let mut min: Option<(f64, usize)> = None;
+        for (cell, entropy) in entropies.iter().enumerate() {
+            if let Some(entropy) = *entropy
+                && min.is_none_or(|(min, _)| entropy < min)
+            {
+                min = Some((entropy, cell));
+            }
+        }
+        min.map(|(_, cell)| cell)
+    }
+
+    #[test]
+    fn min_matches_scan() {
+        for seed in 0..20 {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let mut heap = EntropyHeap::new(CELLS);
+            let mut entropies = vec![None; CELLS];
+            for _ in 0..2000 {
+                let cell = rng.random_range(0..CELLS);
+                // Few distinct values, so that ties are common.
+                let entropy = rng.random_range(0..8) as f64 / 2.0;
+                match rng.random_range(0..20) {
+                    0..12 => {
+                        heap.set(cell, entropy);
+                        entropies[cell] = Some(entropy);
+                    }
+                    12..19 => {
+                        heap.remove(cell);
+                        entropies[cell] = None;
+                    }
+                    _ => {
+                        for entropy in &mut entropies {
+                            if rng.random_bool(0.5) {
+                                *entropy = Some(rng.random_range(0..8) as f64 / 2.0);
+                            } else if rng.random_bool(0.5) {
+                                *entropy = None;
+                            }
+                        }
+                        heap.rebuild(
+                            entropies
+                                .iter()
+                                .enumerate()
+                                .filter_map(|(cell, entropy)| {
+                                    entropy.map(|entropy| (entropy, cell))
+                                })
+                                .collect(),
+                        );
+                    }
+                }
+                assert_eq!(heap.min(), scan(&entropies), "seed {}", seed);
+            }
+            // Emptying the heap in order checks all of it, not only the top.
+            while let Some(cell) = heap.min() {
+                assert_eq!(Some(cell), scan(&entropies), "seed {}", seed);
+                heap.remove(cell);
+                entropies[cell] = None;
+            }
+            assert_eq!(scan(&entropies), None, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn stale_cells_are_taken_once() {
+        let mut heap = EntropyHeap::new(CELLS);
+        assert!(heap.is_all_stale());
+        heap.invalidate(3);
+        assert!(heap.take_stale().is_empty());
+        heap.rebuild(vec![(1.0, 3), (2.0, 4)]);
+        assert!(!heap.is_all_stale());
+        heap.invalidate(4);
+        heap.invalidate(3);
+        heap.invalidate(4);
+        assert_eq!(heap.take_stale(), [4, 3]);
+        assert!(heap.take_stale().is_empty());
+        heap.invalidate(4);
+        heap.invalidate_all();
+        assert!(heap.is_all_stale());
+        assert!(heap.take_stale().is_empty());
+    }
+}
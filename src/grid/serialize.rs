@@ -0,0 +1,298 @@
+use super::Grid;
+use crate::error::{Error, Result};
+use crate::hierarchy::{Guide, GuideMode};
+use crate::tileset::{Neighborhood, TileSet};
+use image::Rgb;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"WFCG";
+const FORMAT_VERSION: u32 = 3;
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> Grid<TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    /// Writes the complete state of the grid, including its tileset, its
+    /// neighborhood, its guide and the position of the RNG, so that
+    /// [`Grid::load`] continues exactly where this grid left off. Constraints
+    /// and the density map are not stored, they are added again on resume.
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let out = self.to_saved().map_err(|e| match e {
+            Error::Encode(message) => Error::encode(path, message),
+            e => e,
+        })?;
+        fs::write(path, out).map_err(|e| Error::io(path, e))
+    }
+
+    fn to_saved(&self) -> Result<Vec<u8>> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.width as u32).to_le_bytes());
+        out.extend_from_slice(&(self.height as u32).to_le_bytes());
+        out.push(self.periodic as u8);
+        let contradiction = self.contradiction.map_or(u32::MAX, |index| index as u32);
+        out.extend_from_slice(&contradiction.to_le_bytes());
+        out.push(self.overlaps.is_some() as u8);
+        out.extend_from_slice(&self.rng.get_seed());
+        out.extend_from_slice(&self.rng.get_stream().to_le_bytes());
+        out.extend_from_slice(&self.rng.get_word_pos().to_le_bytes());
+
+        let tileset = self.tileset.to_bytes(0)?;
+        out.extend_from_slice(&(tileset.len() as u64).to_le_bytes());
+        out.extend_from_slice(&tileset);
+
+        match &self.guide {
+            None => out.push(0),
+            Some(guide) => {
+                match guide.mode {
+                    GuideMode::Soft(weight) => {
+                        out.push(1);
+                        out.extend_from_slice(&weight.to_le_bytes());
+                    }
+                    GuideMode::Hard => out.push(2),
+                }
+                for color in &guide.colors {
+                    out.extend_from_slice(&color.0);
+                }
+                out.extend_from_slice(&(guide.pinned.len() as u32).to_le_bytes());
+                for &index in &guide.pinned {
+                    out.extend_from_slice(&(index as u32).to_le_bytes());
+                }
+            }
+        }
+
+        // Domains are stored as bit masks to keep 4k x 4k grids manageable.
+        let mask_len = self.tileset.len().div_ceil(8);
+        for (index, cell) in self.grid.iter().enumerate() {
+            let final_tile = cell.final_tile.map_or(u32::MAX, |tile| tile as u32);
+            out.extend_from_slice(&final_tile.to_le_bytes());
+            out.push(self.uncollapsed.contains(index) as u8);
+            let mut mask = vec![0u8; mask_len];
+            for option in cell.options.iter() {
+                mask[option / 8] |= 1 << (option % 8);
+            }
+            out.extend_from_slice(&mask);
+        }
+        Ok(out)
+    }
+
+    /// Restores a grid written by [`Grid::save`].
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| Error::io(path, e))?;
+        Self::from_saved(&bytes).map_err(|e| match e {
+            Error::Decode(message) => Error::decode(path, message),
+            e => e,
+        })
+    }
+
+    fn from_saved(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(Error::Decode("not a saved grid".to_string()));
+        }
+        let version = reader.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::Decode(format!(
+                "unsupported grid version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let (width, height) = (reader.u32()? as usize, reader.u32()? as usize);
+        let periodic = reader.u8()? != 0;
+        let contradiction = reader.u32()?;
+        let neighborhood = if reader.u8()? != 0 {
+            Neighborhood::Full
+        } else {
+            Neighborhood::Cardinal
+        };
+        let mut rng = ChaCha8Rng::from_seed(reader.array()?);
+        rng.set_stream(reader.u64()?);
+        rng.set_word_pos(u128::from_le_bytes(reader.array()?));
+
+        let tileset_len = reader.u64()? as usize;
+        let (tileset, _) = TileSet::from_bytes(reader.take(tileset_len)?)?;
+        let tile_count = tileset.len();
+        let mask_len = tile_count.div_ceil(8);
+        // Check the size against the payload before allocating anything for
+        // it, a corrupt header must not ask for gigabytes.
+        let cells = width
+            .checked_mul(height)
+            .filter(|&cells| {
+                cells
+                    .checked_mul(4 + 1 + mask_len)
+                    .is_some_and(|len| len <= reader.bytes.len())
+            })
+            .ok_or_else(|| {
+                Error::Decode(format!(
+                    "grid size {}x{} does not match the file",
+                    width, height
+                ))
+            })?;
+        if contradiction != u32::MAX && contradiction as usize >= cells {
+            return Err(Error::Decode("invalid contradiction cell".to_string()));
+        }
+
+        let mode = match reader.u8()? {
+            0 => None,
+            1 => Some(GuideMode::Soft(f64::from_le_bytes(reader.array()?))),
+            2 => Some(GuideMode::Hard),
+            tag => return Err(Error::Decode(format!("invalid guide mode {}", tag))),
+        };
+        let guide = match mode {
+            None => None,
+            Some(mode) => {
+                let colors = (0..cells)
+                    .map(|_| reader.array().map(Rgb))
+                    .collect::<Result<_>>()?;
+                let pinned = (0..reader.u32()?)
+                    .map(|_| reader.u32().map(|index| index as usize))
+                    .collect::<Result<Vec<_>>>()?;
+                if pinned.iter().any(|&index| index >= cells) {
+                    return Err(Error::Decode("invalid pinned guide cell".to_string()));
+                }
+                Some(Guide {
+                    colors,
+                    mode,
+                    pinned,
+                })
+            }
+        };
+
+        let mut grid = Grid::with_seed(tileset, width, height, 0)?;
+        grid.periodic = periodic;
+        grid.set_neighborhood(neighborhood);
+        // The saved domains already follow the guide, it only has to be
+        // applied again after a reset.
+        grid.guide = guide;
+        grid.rng = rng;
+        grid.uncollapsed.clear();
+        for (index, cell) in grid.grid.iter_mut().enumerate() {
+            let final_tile = reader.u32()?;
+            cell.final_tile = (final_tile != u32::MAX).then_some(final_tile as usize);
+            if reader.u8()? != 0 {
+                grid.uncollapsed.insert(index);
+            }
+            let mask = reader.take(mask_len)?;
+            cell.options = (0..tile_count)
+                .filter(|&option| mask[option / 8] & (1 << (option % 8)) != 0)
+                .collect();
+            if cell.final_tile.is_some_and(|tile| tile >= tile_count) {
+                return Err(Error::Decode(format!("invalid tile in cell {}", index)));
+            }
+        }
+        grid.contradiction = (contradiction != u32::MAX).then_some(contradiction as usize);
+        Ok(grid)
+    }
+}
+
+/// Sequential little-endian reader over a saved grid.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::Decode("truncated grid file".to_string()));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn array<const LEN: usize>(&mut self) -> Result<[u8; LEN]> {
+        let mut array = [0; LEN];
+        array.copy_from_slice(self.take(LEN)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tileset::Extraction;
+    use image::RgbImage;
+
+    fn grid() -> Grid<2, 2> {
+        let colors = [Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([255, 0, 0])];
+        let sample = RgbImage::from_fn(6, 6, |x, y| colors[((x + 2 * y) % 3) as usize]);
+        let tileset = TileSet::new(&sample, Extraction::default()).unwrap();
+        Grid::with_seed(tileset, 8, 6, 7).unwrap()
+    }
+
+    fn tiles(grid: &Grid<2, 2>) -> Vec<Option<usize>> {
+        (0..grid.width * grid.height)
+            .map(|index| grid.get_final_tile(index))
+            .collect()
+    }
+
+    #[test]
+    fn restores_a_grid_that_continues_identically() {
+        let mut grid = grid();
+        grid.set_periodic(false);
+        grid.guide = Some(Guide {
+            colors: vec![Rgb([255, 0, 0]); grid.width * grid.height],
+            mode: GuideMode::Soft(0.5),
+            pinned: vec![3, 9],
+        });
+        for _ in 0..10 {
+            grid.collapse_step().unwrap();
+        }
+        let mut restored = Grid::<2, 2>::from_saved(&grid.to_saved().unwrap()).unwrap();
+        assert_eq!(tiles(&restored), tiles(&grid));
+        assert_eq!(restored.uncollapsed, grid.uncollapsed);
+        assert_eq!(restored.periodic, grid.periodic);
+        assert_eq!(restored.contradiction, None);
+        for (restored, cell) in restored.grid.iter().zip(&grid.grid) {
+            assert_eq!(restored.options, cell.options);
+        }
+        let guide = restored.guide.as_ref().unwrap();
+        assert!(matches!(guide.mode, GuideMode::Soft(weight) if weight == 0.5));
+        assert_eq!(guide.pinned, [3, 9]);
+
+        grid.run_to_completion().unwrap();
+        restored.run_to_completion().unwrap();
+        assert_eq!(tiles(&restored), tiles(&grid));
+    }
+
+    #[test]
+    fn keeps_the_contradiction_cell() {
+        let mut grid = grid();
+        grid.contradiction = Some(17);
+        let restored = Grid::<2, 2>::from_saved(&grid.to_saved().unwrap()).unwrap();
+        assert_eq!(restored.contradiction, Some(17));
+    }
+
+    #[test]
+    fn rejects_sizes_beyond_the_payload() {
+        let mut bytes = grid().to_saved().unwrap();
+        // Width and height follow the magic and the version.
+        bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Grid::<2, 2>::from_saved(&bytes),
+            Err(Error::Decode(_))
+        ));
+
+        let bytes = grid().to_saved().unwrap();
+        assert!(matches!(
+            Grid::<2, 2>::from_saved(&bytes[..bytes.len() - 1]),
+            Err(Error::Decode(_))
+        ));
+    }
+}
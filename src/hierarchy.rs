@@ -0,0 +1,213 @@
+use crate::error::{Error, Result};
+use crate::grid::Grid;
+use crate::tileset::{Sample, SampleSpec, TileSet};
+use image::{Rgb, RgbImage};
+use std::collections::HashMap;
+
+/// How often the coarse grid is regenerated with a fresh seed before giving
+/// up.
+const MAX_ATTEMPTS: usize = 10;
+
+/// Weight multiplier of patterns that match the guide color of a cell in
+/// [`GuideMode::Soft`].
+pub(crate) const SOFT_GUIDE_WEIGHT: f64 = 8.0;
+
+/// How a coarse layout steers the generation of a fine grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GuideMode {
+    /// Patterns of the guide color are preferred when a cell is observed,
+    /// by the given weight multiplier.
+    Soft(f64),
+    /// The cell at the center of every coarse block inside a region of one
+    /// color is pinned to that color, everything else is left to the fine
+    /// patterns.
+    Hard,
+}
+
+/// Colors a fine grid should follow, one per cell, e.g. the upscaled result
+/// of a coarse grid.
+#[derive(Clone, Debug)]
+pub(crate) struct Guide {
+    pub(crate) colors: Vec<Rgb<u8>>,
+    pub(crate) mode: GuideMode,
+    /// Cells constrained in [`GuideMode::Hard`].
+    pub(crate) pinned: Vec<usize>,
+}
+
+impl Guide {
+    /// Upscales the colors of a collapsed coarse grid by `factor` to a fine
+    /// grid of `width` x `height` cells.
+    pub(crate) fn from_coarse<const N: usize>(
+        coarse: &Grid<N, N>,
+        factor: usize,
+        width: usize,
+        height: usize,
+        mode: GuideMode,
+    ) -> Self
+    where
+        [(); N * N]:,
+    {
+        let (coarse_width, coarse_height) = (coarse.width(), coarse.height());
+        let block_color = |bx: usize, by: usize| {
+            let index = bx.min(coarse_width - 1) + by.min(coarse_height - 1) * coarse_width;
+            coarse.get_final_tile(index).map_or(Rgb([0, 0, 0]), |tile| {
+                coarse.tileset.get_tile(tile).get_rgb()
+            })
+        };
+        let colors = (0..width * height)
+            .map(|index| block_color(index % width / factor, index / width / factor))
+            .collect();
+        // Blocks at the edge of a region of their color follow the fine
+        // patterns only roughly, so only the centers of blocks surrounded by
+        // blocks of the same color are pinned.
+        let inside_region = |bx: usize, by: usize| {
+            let color = block_color(bx, by);
+            let around = |b: usize, len: usize| b.saturating_sub(1)..(b + 2).min(len);
+            around(by, coarse_height)
+                .all(|y| around(bx, coarse_width).all(|x| block_color(x, y) == color))
+        };
+        let mut pinned = Vec::new();
+        for y in (factor / 2..height).step_by(factor) {
+            for x in (factor / 2..width).step_by(factor) {
+                if inside_region(x / factor, y / factor) {
+                    pinned.push(x + y * width);
+                }
+            }
+        }
+        Self {
+            colors,
+            mode,
+            pinned,
+        }
+    }
+
+    /// Weight multiplier of a pattern of color `color` in cell `index`.
+    pub(crate) fn weight(&self, index: usize, color: Rgb<u8>) -> f64 {
+        match self.mode {
+            GuideMode::Soft(weight) if self.colors[index] == color => weight,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Shrinks `image` by `factor`, giving every block of `factor` x `factor`
+/// pixels its most common color. Unlike averaging, this keeps the palette of
+/// the sample, so the colors of the coarse patterns exist at the fine level.
+pub(crate) fn downsample(image: &RgbImage, factor: u32) -> RgbImage {
+    let width = image.width().div_ceil(factor);
+    let height = image.height().div_ceil(factor);
+    RgbImage::from_fn(width, height, |x, y| {
+        let mut counts: HashMap<Rgb<u8>, usize> = HashMap::new();
+        let mut best = (0, *image.get_pixel(x * factor, y * factor));
+        for py in y * factor..((y + 1) * factor).min(image.height()) {
+            for px in x * factor..((x + 1) * factor).min(image.width()) {
+                let pixel = *image.get_pixel(px, py);
+                let count = counts.entry(pixel).or_default();
+                *count += 1;
+                if *count > best.0 {
+                    best = (*count, pixel);
+                }
+            }
+        }
+        best.1
+    })
+}
+
+/// Generates the coarse level of a hierarchical generation: learns the
+/// patterns of the samples downsampled by `factor` and collapses a grid that
+/// covers `width` x `height` fine cells.
+pub(crate) fn generate_coarse<const N: usize>(
+    samples: &[SampleSpec],
+    symmetry: usize,
+    factor: usize,
+    (width, height): (usize, usize),
+    periodic: bool,
+    seed: u64,
+) -> Result<Grid<N, N>>
+where
+    [(); N * N]:,
+{
+    let mut images = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let image = image::open(&sample.path)
+            .map_err(|e| Error::image(&sample.path, e))?
+            .into_rgb8();
+        images.push(downsample(&image, factor as u32));
+    }
+    let images: Vec<Sample> = samples
+        .iter()
+        .zip(&images)
+        .map(|(sample, image)| Sample {
+            image,
+            periodic: sample.periodic,
+            weight: sample.weight,
+        })
+        .collect();
+    let tileset = TileSet::from_samples(&images, symmetry)?;
+    let mut grid = Grid::with_seed(
+        tileset,
+        width.div_ceil(factor),
+        height.div_ceil(factor),
+        seed,
+    )?;
+    grid.set_periodic(periodic);
+    for event in grid.generate(MAX_ATTEMPTS - 1) {
+        event?;
+    }
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tileset::Extraction;
+
+    #[test]
+    fn hard_guide_pins_region_interiors_and_completes() {
+        // Squares of 4 x 4 pixels in two colors, like a large checkerboard.
+        let colors = [Rgb([200, 40, 40]), Rgb([40, 40, 200])];
+        let sample = RgbImage::from_fn(16, 16, |x, y| colors[((x / 4 + y / 4) % 2) as usize]);
+        let factor = 2;
+        let (width, height) = (24, 24);
+        let coarse_tileset =
+            TileSet::<3, 3>::new(&downsample(&sample, factor as u32), Extraction::default())
+                .unwrap();
+        let mut coarse =
+            Grid::with_seed(coarse_tileset, width / factor, height / factor, 1).unwrap();
+        coarse.run_to_completion().unwrap();
+        let guide = Guide::from_coarse(&coarse, factor, width, height, GuideMode::Hard);
+        assert!(!guide.pinned.is_empty());
+        // Only blocks inside a region of their color are pinned.
+        for &index in &guide.pinned {
+            let (x, y) = (index % width, index / width);
+            for (dx, dy) in [
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ] {
+                let nx = x.checked_add_signed(dx * factor as isize);
+                let ny = y.checked_add_signed(dy * factor as isize);
+                if let (Some(nx), Some(ny)) = (nx, ny)
+                    && nx < width
+                    && ny < height
+                {
+                    assert_eq!(guide.colors[nx + ny * width], guide.colors[index]);
+                }
+            }
+        }
+
+        let tileset = TileSet::<3, 3>::new(&sample, Extraction::default()).unwrap();
+        let mut grid = Grid::with_seed(tileset, width, height, 1).unwrap();
+        grid.set_guide(guide.clone()).unwrap();
+        grid.run_to_completion().unwrap();
+        for &index in &guide.pinned {
+            let tile = grid.get_final_tile(index).unwrap();
+            assert_eq!(grid.tileset.get_tile(tile).get_rgb(), guide.colors[index]);
+        }
+    }
+}
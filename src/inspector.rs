@@ -0,0 +1,199 @@
+use crate::app::Key;
+use crate::render::{Color, Rect, Renderer};
+use crate::tileset::{Direction, TileSet};
+
+/// Size of one pattern pixel in the inspector.
+const SCALE: u32 = 8;
+/// Space between two patterns of a gallery.
+const GAP: i32 = 8;
+/// Minimal width of a gallery slot, so that weights fit below the pattern.
+const MIN_SLOT_WIDTH: i32 = 48;
+const LABEL_HEIGHT: i32 = 20;
+/// Top of the inspector, below the status line of the viewer.
+const TOP: i32 = 60;
+
+/// Browses the patterns of a tileset: a paged gallery with the weight of every
+/// pattern on the left, the allowed neighbors of the selected pattern in one
+/// direction on the right.
+pub(crate) struct Inspector {
+    selected: usize,
+    /// First pattern on the current page, always at the start of a row.
+    first: usize,
+    direction: Direction,
+    /// Gallery layout of the last frame, used for paging and mouse selection.
+    columns: usize,
+    rows: usize,
+}
+
+impl Inspector {
+    pub(crate) fn new() -> Self {
+        Self {
+            selected: 0,
+            first: 0,
+            direction: Direction::North,
+            columns: 1,
+            rows: 1,
+        }
+    }
+
+    pub(crate) fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Handles a key press and returns whether the key belongs to the
+    /// inspector.
+    pub(crate) fn handle_key(&mut self, key: Key, tile_count: usize) -> bool {
+        let page = (self.columns * self.rows) as isize;
+        let delta = match key {
+            Key::Left => -1,
+            Key::Right => 1,
+            Key::Up => -(self.columns as isize),
+            Key::Down => self.columns as isize,
+            Key::PageUp => -page,
+            Key::PageDown => page,
+            Key::Home => -(tile_count as isize),
+            Key::End => tile_count as isize,
+            Key::Tab => {
+                self.direction = self.direction.clockwise();
+                return true;
+            }
+            _ => return false,
+        };
+        self.select(self.selected as isize + delta, tile_count);
+        true
+    }
+
+    /// Scrolls the gallery by `rows` rows, keeping the selection on the page.
+    pub(crate) fn scroll(&mut self, rows: isize, tile_count: usize) {
+        let last_row = (tile_count.saturating_sub(1) / self.columns) as isize;
+        let row = (self.first / self.columns) as isize + rows;
+        self.first = row.clamp(0, last_row) as usize * self.columns;
+        let last = self.first + self.columns * self.rows - 1;
+        self.selected = self.selected.clamp(self.first, last.min(tile_count - 1));
+    }
+
+    /// Selects the pattern under the window position `(x, y)`, if any.
+    pub(crate) fn click(&mut self, x: i32, y: i32, tile_count: usize, tile_size: (usize, usize)) {
+        let (slot_width, slot_height) = slot_size(tile_size);
+        let (column, row) = (
+            (x - GAP) / slot_width,
+            (y - TOP - LABEL_HEIGHT) / slot_height,
+        );
+        if x < GAP || y < TOP + LABEL_HEIGHT || column as usize >= self.columns {
+            return;
+        }
+        let index = self.first + row as usize * self.columns + column as usize;
+        if index < tile_count {
+            self.selected = index;
+        }
+    }
+
+    fn select(&mut self, index: isize, tile_count: usize) {
+        self.selected = index.clamp(0, tile_count as isize - 1) as usize;
+        let page = self.columns * self.rows;
+        if self.selected < self.first {
+            self.first = self.selected - self.selected % self.columns;
+        } else if self.selected >= self.first + page {
+            self.first = (self.selected / self.columns + 1 - self.rows) * self.columns;
+        }
+    }
+
+    pub(crate) fn draw<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        &mut self,
+        renderer: &mut dyn Renderer,
+        tileset: &TileSet<TILE_WIDTH, TILE_HEIGHT>,
+    ) where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        let (width, height) = renderer.size();
+        let (width, height) = (width as i32, height as i32);
+        let (slot_width, slot_height) = slot_size((TILE_WIDTH, TILE_HEIGHT));
+        let gallery_width = width / 2;
+        self.columns = ((gallery_width - GAP) / slot_width).max(1) as usize;
+        self.rows = ((height - TOP - LABEL_HEIGHT) / slot_height).max(1) as usize;
+        // The window may have been resized since the last frame.
+        self.select(self.selected as isize, tileset.len());
+
+        let page = self.columns * self.rows;
+        renderer.draw_text(&format!(
+                "{} patterns | page {} of {} | arrows, PgUp/PgDn, wheel: select | Tab: direction | I: close",
+                tileset.len(),
+                self.first / page + 1,
+                tileset.len().div_ceil(page)
+            ),
+            GAP,
+            TOP, Color::WHITE);
+        let gallery = (self.first..tileset.len()).take(page);
+        for (slot, index) in gallery.enumerate() {
+            let x = GAP + (slot % self.columns) as i32 * slot_width;
+            let y = TOP + LABEL_HEIGHT + (slot / self.columns) as i32 * slot_height;
+            let tile = tileset.get_tile(index);
+            tile.draw(renderer, x, y, SCALE);
+            if index == self.selected {
+                let outline = Rect::new(
+                    x - 2,
+                    y - 2,
+                    (TILE_WIDTH as u32 * SCALE) + 4,
+                    (TILE_HEIGHT as u32 * SCALE) + 4,
+                );
+                renderer.draw_rect(outline, Color::YELLOW);
+            }
+            let label_y = y + (TILE_HEIGHT as u32 * SCALE) as i32;
+            renderer.draw_text(&tile.get_frequency().to_string(), x, label_y, Color::WHITE);
+        }
+
+        // The selected pattern and its neighbors in the current direction.
+        let x = gallery_width + GAP;
+        let tile = tileset.get_tile(self.selected);
+        tile.draw(renderer, x, TOP + LABEL_HEIGHT, 2 * SCALE);
+        let neighbors = tile.get_neighbors(self.direction);
+        let info_x = x + (TILE_WIDTH as u32 * 2 * SCALE) as i32 + GAP;
+        renderer.draw_text(
+            &format!("pattern {}", self.selected),
+            info_x,
+            TOP + LABEL_HEIGHT,
+            Color::WHITE,
+        );
+        renderer.draw_text(
+            &format!("weight {}", tile.get_frequency()),
+            info_x,
+            TOP + 2 * LABEL_HEIGHT,
+            Color::WHITE,
+        );
+        renderer.draw_text(
+            &format!("{:?}: {} allowed", self.direction, neighbors.len()),
+            info_x,
+            TOP + 3 * LABEL_HEIGHT,
+            Color::WHITE,
+        );
+
+        let top = TOP
+            + LABEL_HEIGHT
+            + ((TILE_HEIGHT as u32 * 2 * SCALE) as i32).max(3 * LABEL_HEIGHT)
+            + GAP;
+        let columns = ((width - x) / slot_width).max(1) as usize;
+        let rows = ((height - top - LABEL_HEIGHT) / slot_height).max(0) as usize;
+        for (slot, index) in neighbors.iter().take(columns * rows).enumerate() {
+            let x = x + (slot % columns) as i32 * slot_width;
+            let y = top + (slot / columns) as i32 * slot_height;
+            tileset.get_tile(index).draw(renderer, x, y, SCALE);
+            let label_y = y + (TILE_HEIGHT as u32 * SCALE) as i32;
+            renderer.draw_text(&index.to_string(), x, label_y, Color::WHITE);
+        }
+        if neighbors.len() > columns * rows {
+            renderer.draw_text(
+                &format!("... and {} more", neighbors.len() - columns * rows),
+                x,
+                top + rows as i32 * slot_height,
+                Color::WHITE,
+            );
+        }
+    }
+}
+
+/// Width and height of one gallery slot for patterns of `tile_size` pixels.
+fn slot_size((tile_width, tile_height): (usize, usize)) -> (i32, i32) {
+    let width = ((tile_width as u32 * SCALE) as i32).max(MIN_SLOT_WIDTH) + GAP;
+    let height = (tile_height as u32 * SCALE) as i32 + LABEL_HEIGHT + GAP;
+    (width, height)
+}
@@ -1,225 +1,407 @@
-#![feature(generic_const_exprs, get_many_mut)]
-
+#![feature(generic_const_exprs)]
+
+mod app;
+mod batch;
+mod constraint;
+mod density;
+mod error;
+mod events;
+mod graph;
 mod grid;
+mod hierarchy;
+mod inspector;
+mod recorder;
+mod render;
+mod stats;
 mod tileset;
-use grid::Grid;
-use image::{ImageReader, RgbImage};
-use sdl2::event::WindowEvent;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::render::{Canvas, TextureCreator};
-use sdl2::ttf::Font;
-use sdl2::video::WindowContext;
-use sdl2::{event::Event, rect::Rect};
-use std::{
-    collections::VecDeque,
-    time::{Duration, Instant},
-};
-use tileset::TileSet;
+mod world;
+use constraint::{CountSpec, Target};
+use std::path::{Path, PathBuf};
+use tileset::{Extraction, Neighborhood, SampleSpec};
 
 const SCALE: u32 = 30;
 const TILE_SIZE: usize = 3;
 const GRID_HEIGHT: usize = 30;
 const GRID_WIDTH: usize = 50;
-
-struct App<'a> {
-    canvas: Canvas<sdl2::video::Window>,
-    texture_creator: TextureCreator<WindowContext>,
-    event_pump: sdl2::EventPump,
-    font: Font<'a, 'a>,
-    n_frame: u32,
-    last_frametime: Instant,
-    frametime_buffer: VecDeque<f32>,
-    last_fps_update: Instant,
-    grid: Grid<TILE_SIZE, TILE_SIZE, GRID_WIDTH, GRID_HEIGHT>,
+const SAMPLE: &str = "samples/Lake.png";
+const FONT: &str = "OpenSans-Regular.ttf";
+const CACHE_DIR: &str = ".wfc-cache";
+
+const USAGE: &str = "\
+usage: wave_function_collapse [options]
+       wave_function_collapse batch <config> <output-dir> [--stats]
+       wave_function_collapse compare <config> [--attempts <k>]
+       wave_function_collapse graph <config> [options]
+       wave_function_collapse world <output> [options]
+
+options:
+  --sample <path>[,weight=<k>][,non-periodic]
+                         sample image (default samples/Lake.png), repeatable
+                         to learn from several images, each counted <k>
+                         times (default 1)
+  -n <n>                 pattern size, 2, 3 or 4 (default 3)
+  --size <width>x<height> output size in cells (default 50x30)
+  --scale <pixels>       size of a cell on screen (default 30)
+  --seed <seed>          seed of the generation (default random)
+  --symmetry <1-8>       rotations and reflections of the patterns (default 1)
+  --non-periodic-input   do not wrap patterns around the sample edges
+  --non-periodic         do not wrap the output around its edges
+  --prune                remove patterns without a neighbor in some direction,
+                         repeatedly, before generating; this renumbers the
+                         patterns, so targets have to be colors
+  --neighborhood <name>  propagate to the cardinal neighbors (default) or to
+                         all cells with overlapping patterns (full)
+  --coarse <factor>      lay out a grid learned from the sample downsampled
+                         by <factor> first and let the output follow it
+  --hard-guide           pin one cell per coarse block inside a region of one
+                         color instead of only preferring the colors of the
+                         coarse layout
+  --count <target>=<min>..<max>
+                         limit the cells of pattern p<index> or of color
+                         #rrggbb, in cells or percent, e.g. #3399ff=..5%
+                         or p12=1..1 (repeatable)
+  --walkable <target>    keep the cells of pattern p<index> or color #rrggbb
+                         connected (repeatable)
+  --endpoint <x>,<y>     only keep these walkable cells connected
+                         (repeatable)
+  --density <path>       prefer patterns of the color of the image stretched
+                         over the output, e.g. forest at the top
+  --density-strength <f> weight of patterns matching the density image
+                         (default 8)
+  --font <path>          font of the overlay (default OpenSans-Regular.ttf)
+  --window               use the software-rendered window instead of SDL
+  --screenshot <path>    generate without a window and save the result
+  --export <dir>         save the patterns as atlas.png and their adjacency
+                         as rules.json to <dir> instead of generating
+  --save-tileset <path>  save the learned tileset to <path>, as JSON if it
+                         ends in .json, instead of generating
+  --resume <path>        continue a saved grid instead of starting anew; its
+                         --count, --walkable and --density have to be given
+                         again
+  --record <path>        record the generation as GIF or PNG frames
+  --record-every <k>     capture a frame every k observations (default 10)
+  --stats                print generation counters on exit
+  --chunks <cols>x<rows> chunks of world, each of --size cells (default 3x3)
+  --origin <cx>,<cy>     first chunk of world (default 0,0)
+  --reverse              generate the chunks of world from the last one, as
+                         chunks continue the ones generated before them
+  --attempts <k>         generations per run and neighborhood of compare
+                         (default 10)
+  --help                 show this message";
+
+/// Command-line options of the viewer.
+///
+/// `batch <config> <output-dir>` runs a batch file instead of opening the
+/// viewer, `compare <config>` compares the neighborhoods on its runs and
+/// `graph <config>` generates the graph file instead of a grid and
+/// `world <output>` saves chunks of an unbounded world, see `World`.
+struct Args {
+    batch: Option<(PathBuf, PathBuf)>,
+    compare: Option<PathBuf>,
+    graph: Option<PathBuf>,
+    world: Option<PathBuf>,
+    chunks: (usize, usize),
+    origin: (i32, i32),
+    reverse: bool,
+    attempts: usize,
+    samples: Vec<SampleSpec>,
+    n: usize,
+    width: usize,
+    height: usize,
+    scale: u32,
+    seed: Option<u64>,
+    extraction: Extraction,
+    periodic: bool,
+    /// Whether dead-end patterns are removed, see `TileSet::prune`.
+    prune: bool,
+    neighborhood: Neighborhood,
+    /// Downsampling factor of the coarse level, see `hierarchy`.
+    coarse: Option<usize>,
+    hard_guide: bool,
+    counts: Vec<CountSpec>,
+    walkable: Vec<Target>,
+    endpoints: Vec<(usize, usize)>,
+    /// Image scaling the pattern weights by position, see `density`.
+    density: Option<PathBuf>,
+    density_strength: f64,
+    font: PathBuf,
+    window: bool,
+    screenshot: Option<PathBuf>,
+    /// Directory the tileset is exported to, see `TileSet::export`.
+    export: Option<PathBuf>,
+    /// File the learned tileset is saved to, see `TileSet::save`.
+    save_tileset: Option<PathBuf>,
+    record: Option<PathBuf>,
+    record_every: usize,
+    resume: Option<PathBuf>,
+    stats: bool,
+    help: bool,
 }
 
-impl<'a> App<'a> {
-    fn new(sdl_context: &sdl2::Sdl, font: Font<'a, 'a>) -> Result<Self, String> {
-        let video_subsystem = sdl_context.video()?;
-
-        // Create a resizable window
-        let window = video_subsystem
-            .window("rust-sdl2 demo: Video", 800, 600)
-            .position_centered()
-            .resizable()
-            .vulkan()
-            .build()
-            .map_err(|e| e.to_string())?;
-
-        // Create a canvas for rendering
-        let canvas = window
-            .into_canvas()
-            // .present_vsync()
-            .build()
-            .map_err(|e| e.to_string())?;
-        let texture_creator = canvas.texture_creator();
-
-        // Initialize the event pump
-        let event_pump = sdl_context.event_pump()?;
-
-        // let image: RgbImage = ImageReader::open("samples/City.png")
-        let image: RgbImage = ImageReader::open("samples/Lake.png")
-            .unwrap()
-            .decode()
-            .unwrap()
-            .into_rgb8();
-
-        Ok(App {
-            canvas,
-            texture_creator,
-            event_pump,
-            font,
-            last_frametime: Instant::now(),
-            frametime_buffer: VecDeque::new(),
-            last_fps_update: Instant::now(),
-            grid: Grid::new(TileSet::new(&image)),
-            n_frame: 0,
-        })
-    }
-
-    fn handle_events(&mut self) -> bool {
-        for event in self.event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => return false,
-
-                Event::KeyDown {
-                    keycode: Some(Keycode::SPACE),
-                    ..
-                } => self.grid.collapse_step(),
-
-                // Handle window resize events
-                Event::Window {
-                    win_event: WindowEvent::Resized(width, height),
-                    ..
-                } => {
-                    // Update the canvas's viewport to match the new window size
-                    self.canvas
-                        .set_viewport(Rect::new(0, 0, width as u32, height as u32));
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Args {
+            batch: None,
+            compare: None,
+            graph: None,
+            world: None,
+            chunks: (3, 3),
+            origin: (0, 0),
+            reverse: false,
+            attempts: 10,
+            samples: Vec::new(),
+            n: TILE_SIZE,
+            width: GRID_WIDTH,
+            height: GRID_HEIGHT,
+            scale: SCALE,
+            seed: None,
+            extraction: Extraction::default(),
+            periodic: true,
+            prune: false,
+            neighborhood: Neighborhood::Cardinal,
+            coarse: None,
+            hard_guide: false,
+            counts: Vec::new(),
+            walkable: Vec::new(),
+            endpoints: Vec::new(),
+            density: None,
+            density_strength: density::DEFAULT_STRENGTH,
+            font: PathBuf::from(FONT),
+            window: !cfg!(feature = "sdl"),
+            screenshot: None,
+            export: None,
+            save_tileset: None,
+            record: None,
+            record_every: 10,
+            resume: None,
+            stats: false,
+            help: false,
+        };
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "batch" => {
+                    let config = iter.next().ok_or("batch expects a config file")?;
+                    let output = iter.next().ok_or("batch expects an output directory")?;
+                    args.batch = Some((PathBuf::from(config), PathBuf::from(output)));
                 }
-
-                _ => {}
+                "compare" => {
+                    let config = iter.next().ok_or("compare expects a config file")?;
+                    args.compare = Some(PathBuf::from(config));
+                }
+                "graph" => {
+                    let config = iter.next().ok_or("graph expects a config file")?;
+                    args.graph = Some(PathBuf::from(config));
+                }
+                "world" => {
+                    let output = iter.next().ok_or("world expects an output image")?;
+                    args.world = Some(PathBuf::from(output));
+                }
+                "--chunks" => {
+                    let chunks = iter.next().ok_or("--chunks expects <cols>x<rows>")?;
+                    let (columns, rows) = chunks
+                        .split_once('x')
+                        .ok_or(format!("invalid --chunks value: {}", chunks))?;
+                    args.chunks = (
+                        parse_value(&arg, Some(columns.to_string()))?,
+                        parse_value(&arg, Some(rows.to_string()))?,
+                    );
+                }
+                "--origin" => {
+                    let origin = iter.next().ok_or("--origin expects <cx>,<cy>")?;
+                    let (x, y) = origin
+                        .split_once(',')
+                        .ok_or(format!("invalid --origin value: {}", origin))?;
+                    args.origin = (
+                        parse_value(&arg, Some(x.to_string()))?,
+                        parse_value(&arg, Some(y.to_string()))?,
+                    );
+                }
+                "--reverse" => args.reverse = true,
+                "--attempts" => args.attempts = parse_value(&arg, iter.next())?,
+                "--sample" => args.samples.push(parse_value(&arg, iter.next())?),
+                "-n" => args.n = parse_value(&arg, iter.next())?,
+                "--size" => {
+                    let size = iter.next().ok_or("--size expects <width>x<height>")?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .ok_or(format!("invalid --size value: {}", size))?;
+                    args.width = parse_value(&arg, Some(width.to_string()))?;
+                    args.height = parse_value(&arg, Some(height.to_string()))?;
+                }
+                "--scale" => args.scale = parse_value(&arg, iter.next())?,
+                "--seed" => args.seed = Some(parse_value(&arg, iter.next())?),
+                "--symmetry" => args.extraction.symmetry = parse_value(&arg, iter.next())?,
+                "--non-periodic-input" => args.extraction.periodic = false,
+                "--non-periodic" => args.periodic = false,
+                "--prune" => args.prune = true,
+                "--neighborhood" => args.neighborhood = parse_value(&arg, iter.next())?,
+                "--coarse" => args.coarse = Some(parse_value(&arg, iter.next())?),
+                "--hard-guide" => args.hard_guide = true,
+                "--count" => args.counts.push(parse_value(&arg, iter.next())?),
+                "--walkable" => args.walkable.push(parse_value(&arg, iter.next())?),
+                "--endpoint" => {
+                    let endpoint = iter.next().ok_or("--endpoint expects <x>,<y>")?;
+                    let (x, y) = endpoint
+                        .split_once(',')
+                        .ok_or(format!("invalid --endpoint value: {}", endpoint))?;
+                    args.endpoints.push((
+                        parse_value(&arg, Some(x.to_string()))?,
+                        parse_value(&arg, Some(y.to_string()))?,
+                    ));
+                }
+                "--density" => {
+                    let path = iter.next().ok_or("--density expects a path")?;
+                    args.density = Some(PathBuf::from(path));
+                }
+                "--density-strength" => args.density_strength = parse_value(&arg, iter.next())?,
+                "--font" => {
+                    let path = iter.next().ok_or("--font expects a path")?;
+                    args.font = PathBuf::from(path);
+                }
+                "--window" => args.window = true,
+                "--screenshot" => {
+                    let path = iter.next().ok_or("--screenshot expects a path")?;
+                    args.screenshot = Some(PathBuf::from(path));
+                }
+                "--export" => {
+                    let path = iter.next().ok_or("--export expects a directory")?;
+                    args.export = Some(PathBuf::from(path));
+                }
+                "--save-tileset" => {
+                    let path = iter.next().ok_or("--save-tileset expects a path")?;
+                    args.save_tileset = Some(PathBuf::from(path));
+                }
+                "--record" => {
+                    let path = iter.next().ok_or("--record expects a path")?;
+                    args.record = Some(PathBuf::from(path));
+                }
+                "--record-every" => args.record_every = parse_value(&arg, iter.next())?,
+                "--stats" => args.stats = true,
+                "--resume" => {
+                    let path = iter.next().ok_or("--resume expects a path")?;
+                    args.resume = Some(PathBuf::from(path));
+                }
+                "-h" | "--help" => args.help = true,
+                _ => return Err(format!("unknown argument: {}\n\n{}", arg, USAGE)),
             }
         }
-        true
-    }
-
-    fn display_fps(&mut self) -> Result<(), String> {
-        self.n_frame += 1;
-        let now = Instant::now();
-        let frametime = now.duration_since(self.last_frametime);
-        self.last_frametime = now;
-
-        // Maintain a buffer of the last 100 frame times
-        if self.frametime_buffer.len() > 100 {
-            self.frametime_buffer.pop_back();
+        if args.samples.is_empty() {
+            args.samples.push(SampleSpec::new(SAMPLE));
         }
-        self.frametime_buffer
-            .push_front(frametime.as_secs_f32() * 1000.0);
-
-        // Calculate the average frame time
-        let frametime_avg =
-            self.frametime_buffer.iter().sum::<f32>() / self.frametime_buffer.len() as f32;
-
-        // Print FPS every second
-        if self.last_fps_update.elapsed() >= Duration::from_secs(1) {
-            println!("FPS: {:.2}", 1000.0 / frametime_avg);
-            self.last_fps_update = now;
+        if !args.extraction.periodic {
+            for sample in &mut args.samples {
+                sample.periodic = false;
+            }
         }
+        if !(2..=4).contains(&args.n) {
+            return Err(format!("-n {} is not supported, use 2, 3 or 4", args.n));
+        }
+        if !(1..=8).contains(&args.extraction.symmetry) {
+            return Err(format!(
+                "--symmetry must be between 1 and 8, got {}",
+                args.extraction.symmetry
+            ));
+        }
+        if args.width == 0 || args.height == 0 || args.scale == 0 {
+            return Err("--size and --scale must be positive".to_string());
+        }
+        if args.coarse.is_some_and(|factor| factor < 2) {
+            return Err("--coarse must be at least 2".to_string());
+        }
+        if args.hard_guide && args.coarse.is_none() {
+            return Err("--hard-guide needs --coarse".to_string());
+        }
+        if !args.endpoints.is_empty() && args.walkable.is_empty() {
+            return Err("--endpoint needs --walkable".to_string());
+        }
+        // Pruning renumbers the patterns, so p<index> would no longer match
+        // the unpruned index the user looked up.
+        let targets = args.counts.iter().map(|count| &count.target);
+        if args.prune
+            && targets
+                .chain(&args.walkable)
+                .any(|target| matches!(target, Target::Pattern(_)))
+        {
+            return Err("--prune renumbers the patterns, select targets by color".to_string());
+        }
+        if !(args.density_strength.is_finite() && args.density_strength >= 1.0) {
+            return Err("--density-strength must be at least 1".to_string());
+        }
+        if args.chunks.0 == 0 || args.chunks.1 == 0 {
+            return Err("--chunks must be positive".to_string());
+        }
+        if args.attempts == 0 {
+            return Err("--attempts must be positive".to_string());
+        }
+        if args.record_every == 0 {
+            return Err("--record-every must be positive".to_string());
+        }
+        Ok(args)
+    }
+}
 
-        // Render FPS to the canvas
-        let fps_text = format!("FPS: {:.2}", 1000.0 / frametime_avg);
-        let surface = self
-            .font
-            .render(&fps_text)
-            .blended(Color::WHITE)
-            .map_err(|e| e.to_string())?;
-        let texture = self
-            .texture_creator
-            .create_texture_from_surface(&surface)
-            .map_err(|e| e.to_string())?;
-
-        // Set the destination rect to position the text at the top-left corner
-        let dest_rect = Rect::new(10, 10, surface.width(), surface.height());
+/// Parses the value following `flag`.
+fn parse_value<T>(flag: &str, value: Option<String>) -> Result<T, String>
+where
+    T: std::str::FromStr<Err: std::fmt::Display>,
+{
+    let value = value.ok_or(format!("{} expects a value", flag))?;
+    value
+        .parse()
+        .map_err(|e| format!("invalid {} value {}: {}", flag, value, e))
+}
 
-        // Copy the texture to the canvas
-        self.canvas.copy(&texture, None, Some(dest_rect))?;
-        Ok(())
+pub(crate) fn main() {
+    if let Err(e) = run() {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
     }
+}
 
-    fn clear_canvas(&mut self) {
-        self.canvas.set_draw_color(Color::BLACK);
-        self.canvas.clear();
+fn run() -> error::Result<()> {
+    let args = Args::parse().map_err(error::Error::Config)?;
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
     }
-
-    fn draw_scene(&mut self) {
-        let grid = &mut self.grid;
-        // let tileset = &grid.tileset;
-        // tileset.draw(&mut self.canvas, 9, SCALE);
-        // let index = (self.n_frame / 10) as usize % (tileset.len() * 4);
-        // let direction = index / tileset.len();
-        // let index = index % tileset.len();
-
-        // tileset.draw_neighbors(
-        //     &mut self.canvas,
-        //     index,
-        //     Direction::VALUES[direction],
-        //     9,
-        //     SCALE,
-        // );
-
-        grid.draw(&mut self.canvas, &self.texture_creator, &self.font, SCALE);
-        for _ in 0..100 {
-            grid.collapse_step();
-        }
-        // let _ = self.canvas.fill_rect(Rect::new(0, 0, 100, 100));
+    if let Some((config, output)) = &args.batch {
+        return batch::run(config, output, Path::new(CACHE_DIR), args.stats);
     }
-
-    fn present_canvas(&mut self) {
-        self.canvas.present();
+    if let Some(config) = &args.compare {
+        return batch::compare(config, Path::new(CACHE_DIR), args.attempts);
     }
-
-    fn update(&mut self) -> Result<(), String> {
-        // Clear canvas, display FPS, and draw scene
-        self.clear_canvas();
-        self.draw_scene();
-        self.display_fps()?;
-        self.present_canvas();
-        Ok(())
+    if let Some(config) = &args.graph {
+        let (samples, symmetry) = (&args.samples, args.extraction.symmetry);
+        let cache_dir = Path::new(CACHE_DIR);
+        return match args.n {
+            2 => graph::run::<2>(config, samples, symmetry, args.seed, cache_dir),
+            3 => graph::run::<3>(config, samples, symmetry, args.seed, cache_dir),
+            4 => graph::run::<4>(config, samples, symmetry, args.seed, cache_dir),
+            n => unreachable!("N = {} is rejected by Args::parse", n),
+        };
     }
-
-    // Main loop
-    fn run(&mut self) -> Result<(), String> {
-        // Main loop
-        'running: loop {
-            // Handle events
-            if !self.handle_events() {
-                break 'running;
-            }
-
-            // Update frame
-            self.update()?;
-        }
-        Ok(())
+    if let Some(output) = &args.world {
+        let (samples, symmetry) = (&args.samples, args.extraction.symmetry);
+        let cache_dir = Path::new(CACHE_DIR);
+        let region = world::Region {
+            chunk_size: (args.width, args.height),
+            origin: args.origin,
+            chunks: args.chunks,
+            reverse: args.reverse,
+        };
+        return match args.n {
+            2 => world::run::<2>(output, samples, symmetry, args.seed, cache_dir, region),
+            3 => world::run::<3>(output, samples, symmetry, args.seed, cache_dir, region),
+            4 => world::run::<4>(output, samples, symmetry, args.seed, cache_dir, region),
+            n => unreachable!("N = {} is rejected by Args::parse", n),
+        };
     }
-}
-
-pub(crate) fn main() -> Result<(), String> {
-    // Initialize SDL2
-    let sdl_context = sdl2::init()?;
-    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
-    let font = ttf_context
-        .load_font("OpenSans-Regular.ttf", 15)
-        .map_err(|e| e.to_string())?;
 
-    let mut app = App::new(&sdl_context, font)?;
-
-    // Start the application main loop
-    app.run()?;
-
-    Ok(())
+    match args.n {
+        2 => app::run::<2>(args),
+        3 => app::run::<3>(args),
+        4 => app::run::<4>(args),
+        n => unreachable!("N = {} is rejected by Args::parse", n),
+    }
 }
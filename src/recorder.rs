@@ -0,0 +1,120 @@
+use crate::error::{Error, Result};
+use crate::events::Event;
+use crate::grid::Grid;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, RgbImage};
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+/// Time each frame of a recorded GIF is shown.
+const FRAME_DELAY_MS: u32 = 50;
+
+/// Captures the state of a `Grid` every `every` observations.
+///
+/// Frames are written as an animated GIF if the output path ends in `.gif`,
+/// otherwise as a numbered PNG sequence into the output directory.
+pub(crate) struct Recorder {
+    output: PathBuf,
+    every: usize,
+    scale: u32,
+    observations: usize,
+    frames: Vec<RgbImage>,
+}
+
+impl Recorder {
+    pub(crate) fn new(output: PathBuf, every: usize, scale: u32) -> Self {
+        Self {
+            output,
+            every: every.max(1),
+            scale,
+            observations: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Starts recording `grid`, capturing its current state as first frame.
+    pub(crate) fn start<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        &mut self,
+        grid: &mut Grid<TILE_WIDTH, TILE_HEIGHT>,
+    ) where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        grid.set_record_events(true);
+        self.frames.push(grid.render(self.scale));
+    }
+
+    /// Consumes the events of `grid` and captures a frame if due. Call this
+    /// after every `collapse_step`.
+    pub(crate) fn update<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        &mut self,
+        grid: &mut Grid<TILE_WIDTH, TILE_HEIGHT>,
+    ) where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        let mut capture = false;
+        for event in grid.drain_events() {
+            match event {
+                Event::Observed { .. } => {
+                    self.observations += 1;
+                    capture |= self.observations.is_multiple_of(self.every);
+                }
+                Event::Finished | Event::Contradiction { .. } | Event::Restart => capture = true,
+                Event::OptionsRemoved { .. } => {}
+            }
+        }
+        if capture {
+            self.frames.push(grid.render(self.scale));
+        }
+    }
+
+    /// Stops recording `grid` and writes all captured frames to disk.
+    pub(crate) fn finish<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+        self,
+        grid: &mut Grid<TILE_WIDTH, TILE_HEIGHT>,
+    ) -> Result<()>
+    where
+        [(); TILE_WIDTH * TILE_HEIGHT]:,
+    {
+        grid.set_record_events(false);
+        let is_gif = self
+            .output
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+        if is_gif {
+            self.write_gif()?;
+        } else {
+            self.write_png_sequence()?;
+        }
+        println!(
+            "Recorded {} frames to {}",
+            self.frames.len(),
+            self.output.display()
+        );
+        Ok(())
+    }
+
+    fn write_gif(&self) -> Result<()> {
+        let file = File::create(&self.output).map_err(|e| Error::io(&self.output, e))?;
+        let mut encoder = GifEncoder::new(file);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|e| Error::image(&self.output, e))?;
+        let delay = Delay::from_numer_denom_ms(FRAME_DELAY_MS, 1);
+        for frame in &self.frames {
+            let rgba = DynamicImage::ImageRgb8(frame.clone()).into_rgba8();
+            encoder
+                .encode_frame(Frame::from_parts(rgba, 0, 0, delay))
+                .map_err(|e| Error::image(&self.output, e))?;
+        }
+        Ok(())
+    }
+
+    fn write_png_sequence(&self) -> Result<()> {
+        fs::create_dir_all(&self.output).map_err(|e| Error::io(&self.output, e))?;
+        for (index, frame) in self.frames.iter().enumerate() {
+            let path = self.output.join(format!("frame_{:05}.png", index));
+            frame.save(&path).map_err(|e| Error::image(&path, e))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+mod buffer;
+#[cfg(feature = "sdl")]
+mod sdl;
+mod window;
+
+pub(crate) use buffer::BufferRenderer;
+#[cfg(feature = "sdl")]
+pub(crate) use sdl::SdlRenderer;
+pub(crate) use window::WindowRenderer;
+
+use image::RgbImage;
+
+/// Point size of the overlay font.
+pub(crate) const FONT_SIZE: u16 = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Color {
+    pub(crate) r: u8,
+    pub(crate) g: u8,
+    pub(crate) b: u8,
+}
+
+impl Color {
+    pub(crate) const BLACK: Self = Self::rgb(0, 0, 0);
+    pub(crate) const WHITE: Self = Self::rgb(255, 255, 255);
+    pub(crate) const RED: Self = Self::rgb(255, 0, 0);
+    pub(crate) const YELLOW: Self = Self::rgb(255, 255, 0);
+    pub(crate) const CYAN: Self = Self::rgb(0, 255, 255);
+    pub(crate) const MAGENTA: Self = Self::rgb(255, 0, 255);
+
+    pub(crate) const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Rect {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl Rect {
+    pub(crate) fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// The drawing operations the viewer needs, so that the same drawing code
+/// renders into an SDL window, a softbuffer window or an offscreen image.
+///
+/// Drawing outside of the target area is clipped; renderers report no
+/// errors, a failed draw call just leaves the target unchanged.
+pub(crate) trait Renderer {
+    /// Width and height of the target in pixels.
+    fn size(&self) -> (u32, u32);
+
+    fn clear(&mut self, color: Color);
+
+    fn fill_rect(&mut self, rect: Rect, color: Color);
+
+    /// Outlines `rect` with a one pixel wide border.
+    fn draw_rect(&mut self, rect: Rect, color: Color) {
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = rect;
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.fill_rect(Rect::new(x, y, width, 1), color);
+        self.fill_rect(Rect::new(x, y + height as i32 - 1, width, 1), color);
+        self.fill_rect(Rect::new(x, y, 1, height), color);
+        self.fill_rect(Rect::new(x + width as i32 - 1, y, 1, height), color);
+    }
+
+    /// Writes a line of text with its top left corner at `(x, y)`.
+    fn draw_text(&mut self, text: &str, x: i32, y: i32, color: Color);
+
+    /// Copies `image` to `(x, y)`, enlarging every pixel to `scale` x `scale`.
+    fn blit(&mut self, image: &RgbImage, x: i32, y: i32, scale: u32);
+}
@@ -0,0 +1,125 @@
+use super::{Color, FONT_SIZE, Rect, Renderer};
+use crate::error::{Error, Result};
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont, point};
+use image::{RgbImage, Rgba, RgbaImage};
+use std::fs;
+use std::path::Path;
+
+/// Renders into an in-memory RGBA image, e.g. for screenshots or to present
+/// it in a window that only accepts pixel buffers.
+pub(crate) struct BufferRenderer {
+    image: RgbaImage,
+    font: Option<FontArc>,
+}
+
+impl BufferRenderer {
+    /// Creates a black image of the given size. Without a font, text is not
+    /// drawn.
+    pub(crate) fn new(width: u32, height: u32, font: Option<FontArc>) -> Self {
+        Self {
+            image: RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255])),
+            font,
+        }
+    }
+
+    pub(crate) fn load_font(path: &Path) -> Result<FontArc> {
+        let bytes = fs::read(path).map_err(|e| Error::io(path, e))?;
+        FontArc::try_from_vec(bytes).map_err(|e| Error::decode(path, e))
+    }
+
+    pub(crate) fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    /// Resizes the image, clearing it to black.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        if self.image.dimensions() != (width, height) {
+            self.image = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+        }
+    }
+
+    /// Mixes `color` into the pixel at `(x, y)` with the given opacity.
+    fn blend(&mut self, x: i32, y: i32, color: Color, alpha: f32) {
+        let (width, height) = self.image.dimensions();
+        if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+            return;
+        }
+        let pixel = self.image.get_pixel_mut(x as u32, y as u32);
+        let alpha = alpha.clamp(0.0, 1.0);
+        for (channel, value) in pixel.0.iter_mut().zip([color.r, color.g, color.b]) {
+            *channel = (*channel as f32 * (1.0 - alpha) + value as f32 * alpha).round() as u8;
+        }
+    }
+}
+
+impl Renderer for BufferRenderer {
+    fn size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn clear(&mut self, color: Color) {
+        for pixel in self.image.pixels_mut() {
+            *pixel = Rgba([color.r, color.g, color.b, 255]);
+        }
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let (width, height) = self.image.dimensions();
+        let x0 = rect.x.clamp(0, width as i32) as u32;
+        let y0 = rect.y.clamp(0, height as i32) as u32;
+        let x1 = (rect.x as i64 + rect.width as i64).clamp(0, width as i64) as u32;
+        let y1 = (rect.y as i64 + rect.height as i64).clamp(0, height as i64) as u32;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.image
+                    .put_pixel(x, y, Rgba([color.r, color.g, color.b, 255]));
+            }
+        }
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32, color: Color) {
+        let Some(font) = self.font.clone() else {
+            return;
+        };
+        // Point sizes are taken at 72 dpi like SDL_ttf does, so both
+        // frontends show text of the same size.
+        let Some(units_per_em) = font.units_per_em() else {
+            return;
+        };
+        let scale = PxScale::from(FONT_SIZE as f32 * font.height_unscaled() / units_per_em);
+        let scaled = font.as_scaled(scale);
+        let baseline = y as f32 + scaled.ascent();
+        let mut caret = x as f32;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(scale, point(caret, baseline));
+            caret += scaled.h_advance(id);
+            previous = Some(id);
+            if let Some(outlined) = font.outline_glyph(glyph) {
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x as i32 + gx as i32;
+                    let py = bounds.min.y as i32 + gy as i32;
+                    self.blend(px, py, color, coverage);
+                });
+            }
+        }
+    }
+
+    fn blit(&mut self, image: &RgbImage, x: i32, y: i32, scale: u32) {
+        for (px, py, pixel) in image.enumerate_pixels() {
+            let [r, g, b] = pixel.0;
+            let rect = Rect::new(
+                x + (px * scale) as i32,
+                y + (py * scale) as i32,
+                scale,
+                scale,
+            );
+            self.fill_rect(rect, Color::rgb(r, g, b));
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use super::{Color, Rect, Renderer};
+use image::RgbImage;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::ttf::Font;
+use sdl2::video::{Window, WindowContext};
+
+/// Renders into an SDL window.
+pub(crate) struct SdlRenderer<'a> {
+    pub(crate) canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+    font: Font<'a, 'a>,
+}
+
+impl<'a> SdlRenderer<'a> {
+    pub(crate) fn new(canvas: Canvas<Window>, font: Font<'a, 'a>) -> Self {
+        let texture_creator = canvas.texture_creator();
+        Self {
+            canvas,
+            texture_creator,
+            font,
+        }
+    }
+
+    pub(crate) fn present(&mut self) {
+        self.canvas.present();
+    }
+}
+
+impl From<Color> for sdl2::pixels::Color {
+    fn from(color: Color) -> Self {
+        Self::RGB(color.r, color.g, color.b)
+    }
+}
+
+impl From<Rect> for sdl2::rect::Rect {
+    fn from(rect: Rect) -> Self {
+        Self::new(rect.x, rect.y, rect.width, rect.height)
+    }
+}
+
+impl Renderer for SdlRenderer<'_> {
+    fn size(&self) -> (u32, u32) {
+        self.canvas.output_size().unwrap_or((0, 0))
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.canvas.set_draw_color(color);
+        self.canvas.clear();
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: Color) {
+        self.canvas.set_draw_color(color);
+        let _ = self.canvas.fill_rect(sdl2::rect::Rect::from(rect));
+    }
+
+    fn draw_rect(&mut self, rect: Rect, color: Color) {
+        self.canvas.set_draw_color(color);
+        let _ = self.canvas.draw_rect(sdl2::rect::Rect::from(rect));
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32, color: Color) {
+        let Ok(surface) = self.font.render(text).blended(color) else {
+            return;
+        };
+        let Ok(texture) = self.texture_creator.create_texture_from_surface(&surface) else {
+            return;
+        };
+        let _ = self.canvas.copy(
+            &texture,
+            None,
+            Some(sdl2::rect::Rect::new(
+                x,
+                y,
+                surface.width(),
+                surface.height(),
+            )),
+        );
+    }
+
+    fn blit(&mut self, image: &RgbImage, x: i32, y: i32, scale: u32) {
+        let (width, height) = image.dimensions();
+        let Ok(mut texture) =
+            self.texture_creator
+                .create_texture_static(PixelFormatEnum::RGB24, width, height)
+        else {
+            return;
+        };
+        if texture
+            .update(None, image.as_raw(), width as usize * 3)
+            .is_err()
+        {
+            return;
+        }
+        let target = sdl2::rect::Rect::new(x, y, width * scale, height * scale);
+        let _ = self.canvas.copy(&texture, None, Some(target));
+    }
+}
@@ -0,0 +1,84 @@
+use super::{BufferRenderer, Color, Rect, Renderer};
+use crate::error::{Error, Result};
+use ab_glyph::FontArc;
+use image::RgbImage;
+use softbuffer::{Context, Surface};
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use winit::window::Window;
+
+/// Renders into a winit window through softbuffer. Frames are drawn into a
+/// `BufferRenderer` and copied to the window by [`WindowRenderer::present`].
+pub(crate) struct WindowRenderer {
+    window: Rc<Window>,
+    surface: Surface<Rc<Window>, Rc<Window>>,
+    buffer: BufferRenderer,
+}
+
+impl WindowRenderer {
+    pub(crate) fn new(window: Rc<Window>, font: FontArc) -> Result<Self> {
+        let context = Context::new(window.clone()).map_err(|e| Error::Window(e.to_string()))?;
+        let surface =
+            Surface::new(&context, window.clone()).map_err(|e| Error::Window(e.to_string()))?;
+        let size = window.inner_size();
+        Ok(Self {
+            window,
+            surface,
+            buffer: BufferRenderer::new(size.width, size.height, Some(font)),
+        })
+    }
+
+    pub(crate) fn window(&self) -> &Window {
+        &self.window
+    }
+
+    /// Adapts the frame to the current window size.
+    pub(crate) fn begin(&mut self) {
+        let size = self.window.inner_size();
+        self.buffer.resize(size.width, size.height);
+    }
+
+    pub(crate) fn present(&mut self) -> Result<()> {
+        let (width, height) = self.buffer.size();
+        let (Some(nonzero_width), Some(nonzero_height)) =
+            (NonZeroU32::new(width), NonZeroU32::new(height))
+        else {
+            // Minimized windows have nothing to show.
+            return Ok(());
+        };
+        self.surface
+            .resize(nonzero_width, nonzero_height)
+            .map_err(|e| Error::Window(e.to_string()))?;
+        let mut target = self
+            .surface
+            .buffer_mut()
+            .map_err(|e| Error::Window(e.to_string()))?;
+        for (target, pixel) in target.iter_mut().zip(self.buffer.image().pixels()) {
+            let [r, g, b, _] = pixel.0;
+            *target = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+        target.present().map_err(|e| Error::Window(e.to_string()))
+    }
+}
+
+impl Renderer for WindowRenderer {
+    fn size(&self) -> (u32, u32) {
+        self.buffer.size()
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.buffer.clear(color);
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: Color) {
+        self.buffer.fill_rect(rect, color);
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32, color: Color) {
+        self.buffer.draw_text(text, x, y, color);
+    }
+
+    fn blit(&mut self, image: &RgbImage, x: i32, y: i32, scale: u32) {
+        self.buffer.blit(image, x, y, scale);
+    }
+}
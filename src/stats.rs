@@ -0,0 +1,44 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Counters collected by a `Grid` while generating, see `Grid::stats`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Stats {
+    pub(crate) observations: u64,
+    pub(crate) queue_pushes: u64,
+    pub(crate) options_removed: u64,
+    pub(crate) contradictions: u64,
+    pub(crate) restarts: u64,
+    pub(crate) min_cell_time: Duration,
+    pub(crate) propagate_time: Duration,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.min_cell_time + self.propagate_time;
+        let share = |part: Duration| {
+            if total.is_zero() {
+                0.0
+            } else {
+                100.0 * part.as_secs_f64() / total.as_secs_f64()
+            }
+        };
+        writeln!(f, "observations:     {}", self.observations)?;
+        writeln!(f, "queue pushes:     {}", self.queue_pushes)?;
+        writeln!(f, "options removed:  {}", self.options_removed)?;
+        writeln!(f, "contradictions:   {}", self.contradictions)?;
+        writeln!(f, "restarts:         {}", self.restarts)?;
+        writeln!(
+            f,
+            "min_cell:         {:.3} ms ({:.1}%)",
+            self.min_cell_time.as_secs_f64() * 1000.0,
+            share(self.min_cell_time)
+        )?;
+        write!(
+            f,
+            "propagate:        {:.3} ms ({:.1}%)",
+            self.propagate_time.as_secs_f64() * 1000.0,
+            share(self.propagate_time)
+        )
+    }
+}
@@ -1,15 +1,25 @@
+mod diagnose;
+mod export;
+mod overlap;
+mod serialize;
+
+pub use overlap::Neighborhood;
+pub(crate) use overlap::Overlaps;
+pub(crate) use serialize::sample_hash;
+
 use std::array::from_fn;
 
+use crate::error::{Error, Result};
+use crate::render::{Color, Rect, Renderer};
 use bittyset::BitSet;
 use image::Rgb;
 use image::RgbImage;
-use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::str::FromStr;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Direction {
     North = 0,
     East = 1,
@@ -19,6 +29,93 @@ pub enum Direction {
 
 impl Direction {
     pub const VALUES: [Self; 4] = [Self::North, Self::East, Self::South, Self::West];
+
+    /// The next direction clockwise.
+    pub fn clockwise(self) -> Self {
+        Self::VALUES[(self as usize + 1) % 4]
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+        }
+    }
+}
+
+/// Parameters of the pattern extraction from a sample image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Extraction {
+    /// Whether patterns wrap around the edges of the sample.
+    pub periodic: bool,
+    /// How many of the eight rotations and reflections of every pattern are
+    /// added to the tileset, from 1 (only the pattern itself) to 8.
+    pub symmetry: usize,
+}
+
+impl Default for Extraction {
+    fn default() -> Self {
+        Self {
+            periodic: true,
+            symmetry: 1,
+        }
+    }
+}
+
+/// One of the images a tileset is learned from.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample<'a> {
+    pub image: &'a RgbImage,
+    /// Whether patterns wrap around the edges of this image.
+    pub periodic: bool,
+    /// How often every occurrence of a pattern in this image is counted.
+    pub weight: u32,
+}
+
+/// A sample image file as given on the command line:
+/// `<path>[,weight=<k>][,non-periodic]`, e.g. `samples/Lake.png,weight=3`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SampleSpec {
+    pub path: PathBuf,
+    pub periodic: bool,
+    pub weight: u32,
+}
+
+impl SampleSpec {
+    /// A periodic sample of weight 1.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            periodic: true,
+            weight: 1,
+        }
+    }
+}
+
+impl FromStr for SampleSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, String> {
+        let mut parts = spec.split(',');
+        let path = parts.next().filter(|path| !path.is_empty());
+        let mut sample =
+            SampleSpec::new(path.ok_or("expected <path>[,weight=<k>][,non-periodic]")?);
+        for option in parts {
+            if option == "non-periodic" {
+                sample.periodic = false;
+            } else if let Some(weight) = option.strip_prefix("weight=") {
+                sample.weight = weight.parse().map_err(|_| "invalid weight")?;
+                if sample.weight == 0 {
+                    return Err("weight must be positive".to_string());
+                }
+            } else {
+                return Err(format!("unknown sample option: {}", option));
+            }
+        }
+        Ok(sample)
+    }
 }
 
 pub struct TileSet<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>
@@ -26,64 +123,152 @@ where
     [(); TILE_WIDTH * TILE_HEIGHT]:,
 {
     tiles: Vec<Tile<TILE_WIDTH, TILE_HEIGHT>>,
+    extraction: Extraction,
+    ground: usize,
 }
 
 impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> TileSet<TILE_WIDTH, TILE_HEIGHT>
 where
     [(); TILE_WIDTH * TILE_HEIGHT]:,
 {
-    pub fn new(image: &RgbImage) -> TileSet<TILE_WIDTH, TILE_HEIGHT> {
-        let width = image.dimensions().0 as usize;
-        let height = image.dimensions().1 as usize;
-        let image: Vec<u32> = image
-            .pixels()
-            .map(|pixel| {
-                let Rgb(data) = pixel;
-                let bytes = [data[0], data[1], data[2], 0]; // Add zero for the alpha channel
-                u32::from_le_bytes(bytes)
-            })
-            .collect();
-        assert!(width - TILE_WIDTH > 0);
-        assert!(height - TILE_HEIGHT > 0);
+    #[allow(dead_code)]
+    pub fn new(
+        image: &RgbImage,
+        extraction: Extraction,
+    ) -> Result<TileSet<TILE_WIDTH, TILE_HEIGHT>> {
+        let sample = Sample {
+            image,
+            periodic: extraction.periodic,
+            weight: 1,
+        };
+        Self::from_samples(&[sample], extraction.symmetry)
+    }
+
+    /// Learns one tileset from several images. Patterns found in more than
+    /// one image are merged and their counts added up, and adjacency is
+    /// derived across all of them, so patterns of different images may end
+    /// up next to each other where their pixels overlap. The ground is taken
+    /// from the first image.
+    pub fn from_samples(
+        samples: &[Sample],
+        symmetry: usize,
+    ) -> Result<TileSet<TILE_WIDTH, TILE_HEIGHT>> {
+        if samples.is_empty() {
+            return Err(Error::EmptyTileSet);
+        }
+        if !(1..=8).contains(&symmetry) || (symmetry > 2 && TILE_WIDTH != TILE_HEIGHT) {
+            return Err(Error::InvalidSymmetry { symmetry });
+        }
         let mut tiles: Vec<Tile<TILE_WIDTH, TILE_HEIGHT>> = Vec::new();
-        for y in 0..height {
-            for x in 0..width {
-                let mut pixels = Vec::new();
-                for dy in 0..TILE_HEIGHT {
-                    for dx in 0..TILE_WIDTH {
-                        let x = (x + dx) % width;
-                        let y = (y + dy) % height;
-                        let index = x + y * width;
-                        pixels.push(image[index]);
+        let mut ground = 0;
+        for (sample_index, sample) in samples.iter().enumerate() {
+            let width = sample.image.dimensions().0 as usize;
+            let height = sample.image.dimensions().1 as usize;
+            if width < TILE_WIDTH || height < TILE_HEIGHT {
+                return Err(Error::SampleTooSmall {
+                    width,
+                    height,
+                    tile_width: TILE_WIDTH,
+                    tile_height: TILE_HEIGHT,
+                });
+            }
+            if sample.weight == 0 {
+                return Err(Error::Config(format!(
+                    "sample {} has a weight of 0",
+                    sample_index + 1
+                )));
+            }
+            let image: Vec<u32> = sample
+                .image
+                .pixels()
+                .map(|pixel| {
+                    let Rgb(data) = pixel;
+                    let bytes = [data[0], data[1], data[2], 0]; // Add zero for the alpha channel
+                    u32::from_le_bytes(bytes)
+                })
+                .collect();
+            let (x_end, y_end) = if sample.periodic {
+                (width, height)
+            } else {
+                (width - TILE_WIDTH + 1, height - TILE_HEIGHT + 1)
+            };
+            for y in 0..y_end {
+                for x in 0..x_end {
+                    let mut pixels = [0; TILE_WIDTH * TILE_HEIGHT];
+                    for (i, pixel) in pixels.iter_mut().enumerate() {
+                        let x = (x + i % TILE_WIDTH) % width;
+                        let y = (y + i / TILE_WIDTH) % height;
+                        *pixel = image[x + y * width];
                     }
-                }
-                let exists = tiles
-                    .iter_mut()
-                    .find_map(|tile| {
-                        if tile.pixels == pixels.as_slice() {
-                            // tile.frequency += 1;
-                            Some(())
-                        } else {
-                            None
+                    for (variant, pixels) in
+                        Self::symmetries(pixels, symmetry).into_iter().enumerate()
+                    {
+                        let index = match tiles.iter().position(|tile| tile.pixels == pixels) {
+                            Some(index) => {
+                                tiles[index].frequency += sample.weight;
+                                index
+                            }
+                            None => {
+                                let mut tile = Tile::new(pixels);
+                                tile.frequency = sample.weight;
+                                tiles.push(tile);
+                                tiles.len() - 1
+                            }
+                        };
+                        // The pattern at the bottom right corner of the sample
+                        // is the ground, like in the reference implementation.
+                        if variant == 0 && sample_index == 0 {
+                            ground = index;
                         }
-                    })
-                    .is_some();
-                // let exists = false;
-                if !exists {
-                    tiles.push(Tile::new(pixels));
+                    }
                 }
             }
         }
-        let mut tile_set = TileSet { tiles };
+        let mut tile_set = TileSet {
+            tiles,
+            extraction: Extraction {
+                periodic: samples.iter().all(|sample| sample.periodic),
+                symmetry,
+            },
+            ground,
+        };
         tile_set.generate_neighbors();
-        tile_set
+        Ok(tile_set)
+    }
+
+    /// Returns `pixels` followed by its reflections and rotations, `symmetry`
+    /// variants in total.
+    fn symmetries(
+        pixels: [u32; TILE_WIDTH * TILE_HEIGHT],
+        symmetry: usize,
+    ) -> Vec<[u32; TILE_WIDTH * TILE_HEIGHT]> {
+        let mut variants = vec![pixels];
+        while variants.len() < symmetry {
+            let last = &variants[variants.len() - 1];
+            let (source, rotated) = if variants.len() % 2 == 1 {
+                (last, false)
+            } else {
+                (&variants[variants.len() - 2], true)
+            };
+            let mut next = [0; TILE_WIDTH * TILE_HEIGHT];
+            for (i, pixel) in next.iter_mut().enumerate() {
+                let (x, y) = (i % TILE_WIDTH, i / TILE_WIDTH);
+                // Rotations are only used for square tiles, see `new`.
+                *pixel = if rotated {
+                    source[TILE_WIDTH - 1 - y + x * TILE_WIDTH]
+                } else {
+                    source[TILE_WIDTH - 1 - x + y * TILE_WIDTH]
+                };
+            }
+            variants.push(next);
+        }
+        variants
     }
 
     fn generate_neighbors(&mut self) {
         for ia in 0..self.tiles.len() {
             for ib in 0..self.tiles.len() {
-                let tile_b = self.tiles[ib].pixels.to_vec();
-                let tile_b = Tile::new(tile_b);
+                let tile_b = Tile::new(self.tiles[ib].pixels);
                 let tile_a = &mut self.tiles[ia];
                 for direction in Direction::VALUES {
                     if tile_a.cmp_adjacent(&tile_b, direction) {
@@ -99,41 +284,34 @@ where
         self.tiles.len()
     }
 
+    /// Index of the pattern forming the bottom row of generated grids when
+    /// ground is enabled.
     #[inline(always)]
-    pub fn get_tile(&self, index: usize) -> &Tile<TILE_WIDTH, TILE_HEIGHT> {
-        &self.tiles[index]
+    pub fn ground(&self) -> usize {
+        self.ground
     }
 
-    #[allow(dead_code)]
-    pub fn draw(&self, canvas: &mut Canvas<Window>, width: usize, scale: u32) {
-        for (index, tile) in self.tiles.iter().enumerate() {
-            let x = (index % width) as i32 * (scale + 3) as i32 * TILE_WIDTH as i32;
-            let y = (index / width) as i32 * (scale + 3) as i32 * TILE_HEIGHT as i32;
-            tile.draw(canvas, x, y, scale);
+    /// All distinct tile colors, in order of first appearance.
+    pub fn colors(&self) -> Vec<Rgb<u8>> {
+        let mut colors = Vec::new();
+        for tile in &self.tiles {
+            let color = tile.get_rgb();
+            if !colors.contains(&color) {
+                colors.push(color);
+            }
         }
+        colors
     }
 
-    pub fn draw_neighbors(
-        &self,
-        canvas: &mut Canvas<Window>,
-        index: usize,
-        direction: Direction,
-        width: usize,
-        scale: u32,
-    ) {
-        let tile = &self.tiles[index];
-        tile.draw(canvas, 0, 0, scale);
-        let mut tiles = Vec::new();
-        for ix in tile.neighbors[direction as usize].iter() {
-            tiles.push(&self.tiles[ix]);
-        }
-        let x_offset = scale as i32 * TILE_WIDTH as i32 + 30;
-        let y_offset = 0;
-        for (index, &tile) in tiles.iter().enumerate() {
-            let x = (index % width) as i32 * (scale + 3) as i32 * TILE_WIDTH as i32 + x_offset;
-            let y = (index / width) as i32 * (scale + 3) as i32 * TILE_HEIGHT as i32 + y_offset;
-            tile.draw(canvas, x, y, scale);
-        }
+    pub fn tiles_with_color(&self, color: Rgb<u8>) -> BitSet {
+        (0..self.tiles.len())
+            .filter(|&index| self.tiles[index].get_rgb() == color)
+            .collect()
+    }
+
+    #[inline(always)]
+    pub fn get_tile(&self, index: usize) -> &Tile<TILE_WIDTH, TILE_HEIGHT> {
+        &self.tiles[index]
     }
 }
 
@@ -143,18 +321,18 @@ where
 {
     pixels: [u32; WIDTH * HEIGHT],
     neighbors: [BitSet<usize>; 4],
-    // frequency: u32,
+    frequency: u32,
 }
 
 impl<const WIDTH: usize, const HEIGHT: usize> Tile<WIDTH, HEIGHT>
 where
     [(); WIDTH * HEIGHT]:,
 {
-    fn new(pixels: Vec<u32>) -> Tile<WIDTH, HEIGHT> {
+    fn new(pixels: [u32; WIDTH * HEIGHT]) -> Tile<WIDTH, HEIGHT> {
         Self {
-            pixels: pixels.try_into().unwrap(),
+            pixels,
             neighbors: from_fn(|_| BitSet::new()),
-            // frequency: 1,
+            frequency: 1,
         }
     }
 
@@ -227,30 +405,43 @@ where
         }
     }
 
+    /// How often the tile occurs in the sample, used as its weight.
+    #[inline(always)]
+    pub(crate) fn get_frequency(&self) -> u32 {
+        self.frequency
+    }
+
     #[inline(always)]
     pub(crate) fn get_neighbors(&self, direction: Direction) -> &BitSet {
         &self.neighbors[direction as usize]
     }
 
-    pub(crate) fn draw(&self, canvas: &mut Canvas<Window>, x: i32, y: i32, scale: u32) {
+    pub(crate) fn draw(&self, renderer: &mut dyn Renderer, x: i32, y: i32, scale: u32) {
+        renderer.blit(&self.to_image(), x, y, scale);
         for tile_x in 0..WIDTH {
             for tile_y in 0..HEIGHT {
-                let color = self.pixels[tile_x + tile_y * WIDTH];
-                let color = Color::from_u32(&PixelFormatEnum::RGBA32.try_into().unwrap(), color);
                 let x = x + tile_x as i32 * scale as i32;
                 let y = y + tile_y as i32 * scale as i32;
-                canvas.set_draw_color(color);
-                let rect = Rect::new(x, y, scale, scale);
-                let _ = canvas.fill_rect(rect);
-                canvas.set_draw_color(Color::WHITE);
-                let _ = canvas.draw_rect(rect);
+                renderer.draw_rect(Rect::new(x, y, scale, scale), Color::WHITE);
             }
         }
     }
 
+    pub(crate) fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(WIDTH as u32, HEIGHT as u32, |x, y| {
+            let [r, g, b, _] = self.pixels[x as usize + y as usize * WIDTH].to_le_bytes();
+            Rgb([r, g, b])
+        })
+    }
+
+    pub(crate) fn get_rgb(&self) -> Rgb<u8> {
+        let [r, g, b, _] = self.pixels[const { WIDTH / 2 }].to_le_bytes();
+        Rgb([r, g, b])
+    }
+
     pub(crate) fn get_color(&self) -> Color {
-        let color = self.pixels[const { WIDTH / 2 }];
-        Color::from_u32(&PixelFormatEnum::RGBA32.try_into().unwrap(), color)
+        let Rgb([r, g, b]) = self.get_rgb();
+        Color::rgb(r, g, b)
     }
 }
 
@@ -262,3 +453,49 @@ where
         self.pixels == other.pixels
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_samples_weight_shared_patterns() {
+        let [black, white, red] = [Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([255, 0, 0])];
+        // Three black pixels and a white one, and two black and two red ones.
+        let first = RgbImage::from_fn(2, 2, |x, y| if (x, y) == (1, 1) { white } else { black });
+        let second = RgbImage::from_fn(2, 2, |x, _| if x == 0 { black } else { red });
+        let samples = [
+            Sample {
+                image: &first,
+                periodic: true,
+                weight: 2,
+            },
+            Sample {
+                image: &second,
+                periodic: true,
+                weight: 3,
+            },
+        ];
+        let tileset = TileSet::<1, 1>::from_samples(&samples, 1).unwrap();
+        let frequency = |color| {
+            let tiles = tileset.tiles_with_color(color);
+            assert_eq!(tiles.len(), 1);
+            let tile = tiles.iter().next().unwrap();
+            tileset.get_tile(tile).get_frequency()
+        };
+        assert_eq!(tileset.len(), 3);
+        assert_eq!(frequency(black), 3 * 2 + 2 * 3);
+        assert_eq!(frequency(white), 2);
+        assert_eq!(frequency(red), 2 * 3);
+        // Patterns of different samples may lie next to each other where
+        // their pixels agree, which for single pixels is anywhere.
+        let red_tile = tileset.tiles_with_color(red).iter().next().unwrap();
+        let white_tile = tileset.tiles_with_color(white).iter().next().unwrap();
+        assert!(
+            tileset
+                .get_tile(white_tile)
+                .get_neighbors(Direction::East)
+                .contains(red_tile)
+        );
+    }
+}
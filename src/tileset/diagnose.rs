@@ -0,0 +1,250 @@
+use super::{Direction, TileSet};
+use bittyset::BitSet;
+
+/// How many patterns a warning lists before it only counts the rest.
+const MAX_LISTED: usize = 10;
+
+/// Patterns of a tileset that generation cannot use freely, see
+/// [`TileSet::diagnose`].
+pub(crate) struct Diagnosis {
+    /// Patterns without any neighbor in some directions. Picking one
+    /// contradicts unless the cell lies at that edge of a non-periodic
+    /// output.
+    dead_ends: Vec<(usize, Vec<Direction>)>,
+    /// Patterns that only neighbor themselves, so they either fill the whole
+    /// output or never appear.
+    isolated: Vec<usize>,
+    ground: usize,
+}
+
+impl Diagnosis {
+    /// One line per kind of problem found, empty if the tileset is sound.
+    pub(crate) fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.dead_ends.is_empty() {
+            let patterns = self.dead_ends.iter().map(|(index, directions)| {
+                let directions: Vec<_> = directions.iter().map(|&d| direction_name(d)).collect();
+                let ground = if *index == self.ground {
+                    ", ground"
+                } else {
+                    ""
+                };
+                format!("p{} ({}{})", index, directions.join(", "), ground)
+            });
+            warnings.push(format!(
+                "{} no neighbor in some direction and cannot be placed away from \
+                 that edge of a non-periodic output: {}",
+                count(self.dead_ends.len(), "pattern has", "patterns have"),
+                list(patterns)
+            ));
+        }
+        if !self.isolated.is_empty() {
+            let patterns = self.isolated.iter().map(|index| format!("p{}", index));
+            warnings.push(format!(
+                "{}: {}",
+                count(
+                    self.isolated.len(),
+                    "pattern only neighbors itself",
+                    "patterns only neighbor themselves"
+                ),
+                list(patterns)
+            ));
+        }
+        warnings
+    }
+}
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> TileSet<TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    /// Finds dead-end and isolated patterns in the adjacency rules.
+    pub(crate) fn diagnose(&self) -> Diagnosis {
+        let mut dead_ends = Vec::new();
+        let mut isolated = Vec::new();
+        for (index, tile) in self.tiles.iter().enumerate() {
+            let directions: Vec<Direction> = Direction::VALUES
+                .into_iter()
+                .filter(|&direction| tile.get_neighbors(direction).is_empty())
+                .collect();
+            if !directions.is_empty() {
+                dead_ends.push((index, directions));
+            } else if tile
+                .neighbors
+                .iter()
+                .all(|neighbors| neighbors.iter().all(|neighbor| neighbor == index))
+            {
+                isolated.push(index);
+            }
+        }
+        Diagnosis {
+            dead_ends,
+            isolated,
+            ground: self.ground,
+        }
+    }
+
+    /// Prunes the tileset if `prune` is set and prints a warning about the
+    /// dead-end and isolated patterns it has to stderr, before generation
+    /// starts.
+    pub(crate) fn check_rules(&mut self, prune: bool) {
+        if prune {
+            let removed = self.prune();
+            if !removed.is_empty() {
+                eprintln!(
+                    "pruned {} dead-end patterns, {} remain",
+                    removed.len(),
+                    self.len()
+                );
+            }
+        }
+        for warning in self.diagnose().warnings() {
+            eprintln!("WARNING: {}", warning);
+        }
+    }
+
+    /// Removes dead-end patterns until every remaining pattern has a
+    /// remaining neighbor in every direction, i.e. makes the rules arc
+    /// consistent, and returns the indices the removed patterns had. The
+    /// remaining patterns are renumbered.
+    ///
+    /// The ground is kept: it is pinned to the bottom row, where it needs no
+    /// neighbor below in a non-periodic output. Isolated patterns are kept
+    /// too, as they are consistent on their own.
+    fn prune(&mut self) -> Vec<usize> {
+        let mut alive: BitSet = (0..self.tiles.len()).collect();
+        loop {
+            let dead: Vec<usize> = alive
+                .iter()
+                .filter(|&index| {
+                    index != self.ground
+                        && self.tiles[index].neighbors.iter().any(|neighbors| {
+                            neighbors.iter().all(|neighbor| !alive.contains(neighbor))
+                        })
+                })
+                .collect();
+            if dead.is_empty() {
+                break;
+            }
+            for index in dead {
+                alive.remove(index);
+            }
+        }
+        let removed: Vec<usize> = (0..self.tiles.len())
+            .filter(|&index| !alive.contains(index))
+            .collect();
+        if removed.is_empty() {
+            return removed;
+        }
+
+        let mut renumbered = vec![usize::MAX; self.tiles.len()];
+        for (new_index, index) in alive.iter().enumerate() {
+            renumbered[index] = new_index;
+        }
+        let tiles = std::mem::take(&mut self.tiles);
+        self.tiles = tiles
+            .into_iter()
+            .enumerate()
+            .filter(|&(index, _)| alive.contains(index))
+            .map(|(_, mut tile)| {
+                for neighbors in &mut tile.neighbors {
+                    *neighbors = neighbors
+                        .iter()
+                        .filter(|&neighbor| alive.contains(neighbor))
+                        .map(|neighbor| renumbered[neighbor])
+                        .collect();
+                }
+                tile
+            })
+            .collect();
+        self.ground = renumbered[self.ground];
+        removed
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::North => "north",
+        Direction::East => "east",
+        Direction::South => "south",
+        Direction::West => "west",
+    }
+}
+
+/// `count` followed by the singular or plural phrase.
+fn count(count: usize, singular: &str, plural: &str) -> String {
+    format!("{} {}", count, if count == 1 { singular } else { plural })
+}
+
+/// Joins `items` with commas, listing at most [`MAX_LISTED`] of them.
+fn list(items: impl ExactSizeIterator<Item = String>) -> String {
+    let count = items.len();
+    let mut listed: Vec<String> = items.take(MAX_LISTED).collect();
+    if count > MAX_LISTED {
+        listed.push(format!("and {} more", count - MAX_LISTED));
+    }
+    listed.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tileset::{Extraction, Tile};
+
+    /// A tileset of single pixels whose pattern `i` has the color `i` and
+    /// the neighbors `neighbors[i]` in the directions north, east, south
+    /// and west.
+    fn tileset(neighbors: &[[&[usize]; 4]], ground: usize) -> TileSet<1, 1> {
+        let tiles = neighbors
+            .iter()
+            .enumerate()
+            .map(|(index, neighbors)| {
+                let mut tile = Tile::new([index as u32]);
+                tile.neighbors = neighbors.map(|neighbors| neighbors.iter().copied().collect());
+                tile
+            })
+            .collect();
+        TileSet {
+            tiles,
+            extraction: Extraction::default(),
+            ground,
+        }
+    }
+
+    fn neighbors(tileset: &TileSet<1, 1>, index: usize) -> [Vec<usize>; 4] {
+        Direction::VALUES.map(|direction| {
+            let tile = tileset.get_tile(index);
+            tile.get_neighbors(direction).iter().collect()
+        })
+    }
+
+    #[test]
+    fn prune_removes_dead_ends_and_renumbers_neighbors() {
+        let mut tileset = tileset(
+            &[
+                // The ground, kept although nothing may lie south of it.
+                [&[0, 3], &[0, 2, 3], &[], &[0, 3]],
+                // Only p2 may lie north of p1, so it dies with p2.
+                [&[2], &[0, 3], &[0, 3], &[0, 3]],
+                // Nothing may lie east of p2.
+                [&[0], &[], &[0], &[0]],
+                [&[0, 1, 3], &[0, 1, 3], &[0, 1, 3], &[0, 1, 3]],
+            ],
+            0,
+        );
+        assert_eq!(tileset.prune(), [1, 2]);
+        assert_eq!(tileset.len(), 2);
+        assert_eq!(tileset.ground, 0);
+        // p3 is now p1, and the pruned patterns are gone from the rules.
+        assert_eq!(tileset.get_tile(1).pixels, [3]);
+        assert_eq!(
+            neighbors(&tileset, 0),
+            [vec![0, 1], vec![0, 1], vec![], vec![0, 1]]
+        );
+        assert_eq!(
+            neighbors(&tileset, 1),
+            [vec![0, 1], vec![0, 1], vec![0, 1], vec![0, 1]]
+        );
+        assert!(tileset.prune().is_empty());
+    }
+}
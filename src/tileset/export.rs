@@ -0,0 +1,141 @@
+use super::{Direction, Tile, TileSet};
+use crate::error::{Error, Result};
+use crate::render::{BufferRenderer, Color, FONT_SIZE, Renderer};
+use ab_glyph::FontArc;
+use image::DynamicImage;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Space below every pattern of the atlas for its label.
+const LABEL_HEIGHT: u32 = FONT_SIZE as u32 + 8;
+/// Narrowest atlas cell, so that labels of small patterns stay readable.
+const MIN_CELL_WIDTH: u32 = 48;
+const MARGIN: u32 = 8;
+
+/// The learned adjacency rules, written for review rather than for loading
+/// back, see [`TileSet::save`] for that.
+#[derive(Serialize)]
+struct Rules {
+    tile_width: usize,
+    tile_height: usize,
+    periodic: bool,
+    symmetry: usize,
+    ground: usize,
+    patterns: Vec<PatternRules>,
+}
+
+#[derive(Serialize)]
+struct PatternRules {
+    index: usize,
+    weight: u32,
+    /// Row-major colors of the pattern as `#rrggbb`.
+    pixels: Vec<String>,
+    neighbors: NeighborRules,
+}
+
+/// Allowed neighbors in every direction, by index of the neighboring pattern.
+#[derive(Serialize)]
+struct NeighborRules {
+    north: Vec<Neighbor>,
+    east: Vec<Neighbor>,
+    south: Vec<Neighbor>,
+    west: Vec<Neighbor>,
+}
+
+#[derive(Serialize)]
+struct Neighbor {
+    pattern: usize,
+    weight: u32,
+}
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> TileSet<TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    /// Writes `atlas.png` and `rules.json` to `dir`: every pattern enlarged
+    /// by `scale` with a pixel grid and its `p<index>` label, and the
+    /// neighbors each pattern allows in every direction with their weights.
+    pub(crate) fn export(&self, dir: &Path, scale: u32, font: Option<FontArc>) -> Result<()> {
+        fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        let atlas = dir.join("atlas.png");
+        DynamicImage::from(self.draw_atlas(scale, font).image().clone())
+            .into_rgb8()
+            .save(&atlas)
+            .map_err(|e| Error::image(&atlas, e))?;
+        let rules = dir.join("rules.json");
+        let bytes =
+            serde_json::to_vec_pretty(&self.rules()).map_err(|e| Error::encode(&rules, e))?;
+        fs::write(&rules, bytes).map_err(|e| Error::io(&rules, e))
+    }
+
+    /// Lays the patterns out in rows of a roughly square atlas, in index
+    /// order.
+    fn draw_atlas(&self, scale: u32, font: Option<FontArc>) -> BufferRenderer {
+        let columns = (self.len() as f64).sqrt().ceil() as u32;
+        let rows = (self.len() as u32).div_ceil(columns);
+        let cell_width = (TILE_WIDTH as u32 * scale).max(MIN_CELL_WIDTH) + MARGIN;
+        let cell_height = TILE_HEIGHT as u32 * scale + LABEL_HEIGHT + MARGIN;
+        let mut renderer = BufferRenderer::new(
+            columns * cell_width + MARGIN,
+            rows * cell_height + MARGIN,
+            font,
+        );
+        for (index, tile) in self.tiles.iter().enumerate() {
+            let x = (MARGIN + index as u32 % columns * cell_width) as i32;
+            let y = (MARGIN + index as u32 / columns * cell_height) as i32;
+            tile.draw(&mut renderer, x, y, scale);
+            let label_y = y + (TILE_HEIGHT as u32 * scale) as i32 + 2;
+            let color = if index == self.ground {
+                Color::YELLOW
+            } else {
+                Color::WHITE
+            };
+            renderer.draw_text(&format!("p{}", index), x, label_y, color);
+        }
+        renderer
+    }
+
+    fn rules(&self) -> Rules {
+        let neighbors = |tile: &Tile<TILE_WIDTH, TILE_HEIGHT>, direction: Direction| {
+            tile.get_neighbors(direction)
+                .iter()
+                .map(|pattern| Neighbor {
+                    pattern,
+                    weight: self.tiles[pattern].frequency,
+                })
+                .collect()
+        };
+        let patterns = self
+            .tiles
+            .iter()
+            .enumerate()
+            .map(|(index, tile)| PatternRules {
+                index,
+                weight: tile.frequency,
+                pixels: tile
+                    .pixels
+                    .iter()
+                    .map(|pixel| {
+                        let [r, g, b, _] = pixel.to_le_bytes();
+                        format!("#{:02x}{:02x}{:02x}", r, g, b)
+                    })
+                    .collect(),
+                neighbors: NeighborRules {
+                    north: neighbors(tile, Direction::North),
+                    east: neighbors(tile, Direction::East),
+                    south: neighbors(tile, Direction::South),
+                    west: neighbors(tile, Direction::West),
+                },
+            })
+            .collect();
+        Rules {
+            tile_width: TILE_WIDTH,
+            tile_height: TILE_HEIGHT,
+            periodic: self.extraction.periodic,
+            symmetry: self.extraction.symmetry,
+            ground: self.ground,
+            patterns,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use super::{Tile, TileSet};
+use bittyset::BitSet;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// The cells a change is propagated to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Neighborhood {
+    /// The four cells sharing an edge, using the adjacency of the tileset.
+    #[default]
+    Cardinal,
+    /// Every cell whose pattern overlaps the pattern of the cell, i.e. all
+    /// offsets within `-N + 1..N`, like the propagator of the reference
+    /// overlapping model. Catches conflicts earlier, at the cost of more
+    /// work per propagated cell.
+    Full,
+}
+
+impl FromStr for Neighborhood {
+    type Err = String;
+
+    fn from_str(neighborhood: &str) -> Result<Self, String> {
+        match neighborhood {
+            "cardinal" => Ok(Neighborhood::Cardinal),
+            "full" => Ok(Neighborhood::Full),
+            _ => Err("neighborhood must be cardinal or full".to_string()),
+        }
+    }
+}
+
+/// Compatible patterns at every offset at which two patterns overlap.
+pub(crate) struct Overlaps {
+    offsets: Vec<(isize, isize)>,
+    /// Patterns that agree with a pattern at an offset, indexed by
+    /// `tile * offsets.len() + offset`.
+    compatible: Vec<BitSet>,
+}
+
+impl Overlaps {
+    /// The offsets, in cells, from a cell to the cells it constrains.
+    #[inline(always)]
+    pub(crate) fn offsets(&self) -> &[(isize, isize)] {
+        &self.offsets
+    }
+
+    /// Patterns allowed at `offsets()[offset]` from a cell with `tile`.
+    #[inline(always)]
+    pub(crate) fn compatible(&self, tile: usize, offset: usize) -> &BitSet {
+        &self.compatible[tile * self.offsets.len() + offset]
+    }
+}
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> TileSet<TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    /// Computes which patterns agree at every offset at which they overlap.
+    /// This compares every pair of patterns at every offset, so it is only
+    /// done on request, see [`Neighborhood::Full`].
+    pub(crate) fn overlaps(&self) -> Overlaps {
+        let mut offsets = Vec::new();
+        for dy in 1 - TILE_HEIGHT as isize..TILE_HEIGHT as isize {
+            for dx in 1 - TILE_WIDTH as isize..TILE_WIDTH as isize {
+                if (dx, dy) != (0, 0) {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        let mut compatible = Vec::with_capacity(self.tiles.len() * offsets.len());
+        for tile in &self.tiles {
+            for &(dx, dy) in &offsets {
+                compatible.push(
+                    (0..self.tiles.len())
+                        .filter(|&other| tile.agrees(&self.tiles[other], dx, dy))
+                        .collect(),
+                );
+            }
+        }
+        Overlaps {
+            offsets,
+            compatible,
+        }
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Tile<WIDTH, HEIGHT>
+where
+    [(); WIDTH * HEIGHT]:,
+{
+    /// Whether `other`, placed `dx`, `dy` pixels away from this tile, has the
+    /// same pixels where the two overlap.
+    fn agrees(&self, other: &Self, dx: isize, dy: isize) -> bool {
+        let xs = dx.max(0)..(WIDTH as isize).min(WIDTH as isize + dx);
+        for ya in dy.max(0)..(HEIGHT as isize).min(HEIGHT as isize + dy) {
+            for xa in xs.clone() {
+                let ia = (xa + ya * WIDTH as isize) as usize;
+                let ib = (xa - dx + (ya - dy) * WIDTH as isize) as usize;
+                if self.pixels[ia] != other.pixels[ib] {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tileset::{Direction, Extraction};
+    use image::{Rgb, RgbImage};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    fn check_cardinal_offsets<const N: usize>(seed: u64)
+    where
+        [(); N * N]:,
+    {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let colors = [Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([255, 0, 0])];
+        let sample = RgbImage::from_fn(7, 6, |_, _| {
+            colors[rng.random_range(0..2 + seed as usize % 2)]
+        });
+        let tileset = TileSet::<N, N>::new(&sample, Extraction::default()).unwrap();
+        let overlaps = tileset.overlaps();
+        for (direction, offset) in [
+            (Direction::North, (0, -1)),
+            (Direction::East, (1, 0)),
+            (Direction::South, (0, 1)),
+            (Direction::West, (-1, 0)),
+        ] {
+            let link = overlaps
+                .offsets()
+                .iter()
+                .position(|&o| o == offset)
+                .unwrap();
+            for tile in 0..tileset.len() {
+                assert_eq!(
+                    overlaps.compatible(tile, link),
+                    tileset.get_tile(tile).get_neighbors(direction)
+                );
+            }
+        }
+        // Agreement is symmetric: b at (dx, dy) from a is a at (-dx, -dy) from b.
+        for (link, &(dx, dy)) in overlaps.offsets().iter().enumerate() {
+            let opposite = overlaps
+                .offsets()
+                .iter()
+                .position(|&o| o == (-dx, -dy))
+                .unwrap();
+            for tile in 0..tileset.len() {
+                for other in overlaps.compatible(tile, link).iter() {
+                    assert!(overlaps.compatible(other, opposite).contains(tile));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cardinal_overlaps_match_the_adjacency_rules() {
+        for seed in 0..4 {
+            check_cardinal_offsets::<2>(seed);
+            check_cardinal_offsets::<3>(seed);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use super::{Extraction, Sample, SampleSpec, Tile, TileSet};
+use crate::error::{Error, Result};
+use bittyset::BitSet;
+use image::{ImageReader, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"WFCT";
+/// Bump whenever the stored data or the extraction changes, so that stale
+/// cache files are relearned instead of being misread.
+const FORMAT_VERSION: u32 = 2;
+
+/// Storage representation of a [`TileSet`], shared by the binary and the
+/// JSON format. Pixels are stored as indices into a common palette.
+#[derive(Serialize, Deserialize)]
+struct TileSetData {
+    version: u32,
+    sample_hash: u64,
+    tile_width: usize,
+    tile_height: usize,
+    periodic: bool,
+    symmetry: usize,
+    ground: usize,
+    palette: Vec<u32>,
+    patterns: Vec<PatternData>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PatternData {
+    pixels: Vec<u16>,
+    weight: u32,
+    neighbors: [Vec<usize>; 4],
+}
+
+/// FNV-1a hash of a sample file, stable across platforms and compilers.
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> TileSet<TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    /// Learns the tileset of a sample image, or loads it from `cache_dir` if
+    /// it was learned before from the same file contents and parameters.
+    pub(crate) fn from_sample_cached(
+        sample: &Path,
+        extraction: Extraction,
+        cache_dir: &Path,
+    ) -> Result<Self> {
+        let sample = SampleSpec {
+            periodic: extraction.periodic,
+            ..SampleSpec::new(sample)
+        };
+        Self::from_samples_cached(&[sample], extraction.symmetry, cache_dir)
+    }
+
+    /// Like [`TileSet::from_sample_cached`] for a tileset learned from
+    /// several images, see [`TileSet::from_samples`].
+    pub(crate) fn from_samples_cached(
+        samples: &[SampleSpec],
+        symmetry: usize,
+        cache_dir: &Path,
+    ) -> Result<Self> {
+        let files = read_samples(samples)?;
+        let sample_hash = hash_samples(samples, &files);
+        let extraction = Extraction {
+            periodic: samples.iter().all(|sample| sample.periodic),
+            symmetry,
+        };
+        let cache_path = Self::cache_path(samples, extraction, cache_dir);
+        if let Ok((tileset, hash)) = Self::load(&cache_path)
+            && hash == sample_hash
+            && tileset.extraction == extraction
+        {
+            return Ok(tileset);
+        }
+
+        let mut images = Vec::with_capacity(samples.len());
+        for (sample, bytes) in samples.iter().zip(files) {
+            images.push(decode(&sample.path, bytes)?);
+        }
+        let images: Vec<Sample> = samples
+            .iter()
+            .zip(&images)
+            .map(|(sample, image)| Sample {
+                image,
+                periodic: sample.periodic,
+                weight: sample.weight,
+            })
+            .collect();
+        let tileset = Self::from_samples(&images, symmetry)?;
+        if let Err(e) = fs::create_dir_all(cache_dir)
+            .map_err(|e| Error::io(cache_dir, e))
+            .and_then(|_| tileset.save(&cache_path, sample_hash))
+        {
+            eprintln!("WARNING: could not cache tileset: {}", e);
+        }
+        Ok(tileset)
+    }
+
+    fn cache_path(samples: &[SampleSpec], extraction: Extraction, cache_dir: &Path) -> PathBuf {
+        let stems: Vec<_> = samples
+            .iter()
+            .map(|sample| {
+                sample
+                    .path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy())
+                    .unwrap_or_default()
+            })
+            .collect();
+        cache_dir.join(format!(
+            "{}-{}x{}-{}{}.wfct",
+            stems.join("+"),
+            TILE_WIDTH,
+            TILE_HEIGHT,
+            if extraction.periodic { "p" } else { "n" },
+            extraction.symmetry
+        ))
+    }
+
+    /// Writes the tileset as JSON if `path` ends in `.json`, otherwise in the
+    /// binary format. `sample_hash` identifies the sample it was learned from.
+    pub(crate) fn save(&self, path: &Path, sample_hash: u64) -> Result<()> {
+        let with_path = |e: Error| match e {
+            Error::Encode(message) => Error::encode(path, message),
+            e => e,
+        };
+        let bytes = if is_json(path) {
+            let data = self.to_data(sample_hash).map_err(with_path)?;
+            serde_json::to_vec_pretty(&data).map_err(|e| Error::encode(path, e))?
+        } else {
+            self.to_bytes(sample_hash).map_err(with_path)?
+        };
+        fs::write(path, bytes).map_err(|e| Error::io(path, e))
+    }
+
+    /// Encodes the tileset in the binary format. Fails if the patterns have
+    /// more colors than the palette can index.
+    pub(crate) fn to_bytes(&self, sample_hash: u64) -> Result<Vec<u8>> {
+        Ok(self.to_data(sample_hash)?.to_binary())
+    }
+
+    /// Decodes a tileset in the binary format, see [`TileSet::to_bytes`].
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<(Self, u64)> {
+        let data = TileSetData::from_binary(bytes)?;
+        let sample_hash = data.sample_hash;
+        Ok((Self::from_data(data)?, sample_hash))
+    }
+
+    /// Reads a tileset written by [`TileSet::save`] together with the hash of
+    /// the sample it was learned from.
+    pub(crate) fn load(path: &Path) -> Result<(Self, u64)> {
+        let bytes = fs::read(path).map_err(|e| Error::io(path, e))?;
+        let with_path = |e: Error| match e {
+            Error::Decode(message) => Error::decode(path, message),
+            e => e,
+        };
+        if !is_json(path) {
+            return Self::from_bytes(&bytes).map_err(with_path);
+        }
+        let data: TileSetData =
+            serde_json::from_slice(&bytes).map_err(|e| Error::decode(path, e))?;
+        let sample_hash = data.sample_hash;
+        Ok((Self::from_data(data).map_err(with_path)?, sample_hash))
+    }
+
+    fn to_data(&self, sample_hash: u64) -> Result<TileSetData> {
+        let mut palette: Vec<u32> = Vec::new();
+        let mut patterns = Vec::with_capacity(self.tiles.len());
+        for tile in &self.tiles {
+            let mut pixels = Vec::with_capacity(tile.pixels.len());
+            for &pixel in &tile.pixels {
+                let index = match palette.iter().position(|&color| color == pixel) {
+                    Some(index) => index,
+                    None => {
+                        palette.push(pixel);
+                        palette.len() - 1
+                    }
+                };
+                let index = u16::try_from(index).map_err(|_| {
+                    Error::Encode(format!(
+                        "tileset has more than {} colors",
+                        u16::MAX as usize + 1
+                    ))
+                })?;
+                pixels.push(index);
+            }
+            patterns.push(PatternData {
+                pixels,
+                weight: tile.frequency,
+                neighbors: tile.neighbors.each_ref().map(|set| set.iter().collect()),
+            });
+        }
+        Ok(TileSetData {
+            version: FORMAT_VERSION,
+            sample_hash,
+            tile_width: TILE_WIDTH,
+            tile_height: TILE_HEIGHT,
+            periodic: self.extraction.periodic,
+            symmetry: self.extraction.symmetry,
+            ground: self.ground,
+            palette,
+            patterns,
+        })
+    }
+
+    fn from_data(data: TileSetData) -> Result<Self> {
+        if data.version != FORMAT_VERSION {
+            return Err(Error::Decode(format!(
+                "unsupported tileset version {} (expected {})",
+                data.version, FORMAT_VERSION
+            )));
+        }
+        if (data.tile_width, data.tile_height) != (TILE_WIDTH, TILE_HEIGHT) {
+            return Err(Error::Decode(format!(
+                "tileset has {}x{} tiles, expected {}x{}",
+                data.tile_width, data.tile_height, TILE_WIDTH, TILE_HEIGHT
+            )));
+        }
+        let count = data.patterns.len();
+        if count == 0 {
+            return Err(Error::EmptyTileSet);
+        }
+        if data.ground >= count {
+            return Err(Error::Decode(
+                "ground pattern outside of the tileset".to_string(),
+            ));
+        }
+        let mut tiles = Vec::with_capacity(count);
+        for pattern in data.patterns {
+            let pixels: Vec<u32> = pattern
+                .pixels
+                .iter()
+                .map(|&index| data.palette.get(index as usize).copied())
+                .collect::<Option<_>>()
+                .ok_or(Error::Decode(
+                    "pattern references a color outside of the palette".to_string(),
+                ))?;
+            let malformed = || Error::Decode("malformed pattern in tileset".to_string());
+            if pattern.neighbors.iter().flatten().any(|&ix| ix >= count) {
+                return Err(malformed());
+            }
+            let pixels = pixels.try_into().map_err(|_| malformed())?;
+            let mut tile = Tile::new(pixels);
+            tile.frequency = pattern.weight;
+            tile.neighbors = pattern
+                .neighbors
+                .map(|neighbors| neighbors.into_iter().collect::<BitSet>());
+            tiles.push(tile);
+        }
+        Ok(TileSet {
+            tiles,
+            extraction: Extraction {
+                periodic: data.periodic,
+                symmetry: data.symmetry,
+            },
+            ground: data.ground,
+        })
+    }
+}
+
+/// The hash the tileset learned from `samples` is cached with, for saving it
+/// elsewhere with [`TileSet::save`].
+pub(crate) fn sample_hash(samples: &[SampleSpec]) -> Result<u64> {
+    Ok(hash_samples(samples, &read_samples(samples)?))
+}
+
+fn read_samples(samples: &[SampleSpec]) -> Result<Vec<Vec<u8>>> {
+    samples
+        .iter()
+        .map(|sample| fs::read(&sample.path).map_err(|e| Error::io(&sample.path, e)))
+        .collect()
+}
+
+/// Identifies the samples a tileset is learned from by their contents and
+/// settings. A single plain sample hashes like its file, so caches of
+/// tilesets learned from one image stay valid.
+fn hash_samples(samples: &[SampleSpec], files: &[Vec<u8>]) -> u64 {
+    if let ([sample], [bytes]) = (samples, files)
+        && sample.weight == 1
+    {
+        return hash_bytes(bytes);
+    }
+    let mut key = Vec::new();
+    for (sample, bytes) in samples.iter().zip(files) {
+        key.extend_from_slice(&hash_bytes(bytes).to_le_bytes());
+        key.extend_from_slice(&sample.weight.to_le_bytes());
+        key.push(sample.periodic as u8);
+    }
+    hash_bytes(&key)
+}
+
+fn decode(path: &Path, bytes: Vec<u8>) -> Result<RgbImage> {
+    Ok(ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| Error::io(path, e))?
+        .decode()
+        .map_err(|e| Error::decode(path, e))?
+        .into_rgb8())
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+impl TileSetData {
+    fn to_binary(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        let mut put = |value: u32| out.extend_from_slice(&value.to_le_bytes());
+        put(self.version);
+        put(self.sample_hash as u32);
+        put((self.sample_hash >> 32) as u32);
+        put(self.tile_width as u32);
+        put(self.tile_height as u32);
+        put(self.periodic as u32);
+        put(self.symmetry as u32);
+        put(self.ground as u32);
+        put(self.palette.len() as u32);
+        self.palette.iter().for_each(|&color| put(color));
+        put(self.patterns.len() as u32);
+        for pattern in &self.patterns {
+            put(pattern.weight);
+            pattern.pixels.iter().for_each(|&pixel| put(pixel as u32));
+            for neighbors in &pattern.neighbors {
+                put(neighbors.len() as u32);
+                neighbors.iter().for_each(|&index| put(index as u32));
+            }
+        }
+        out
+    }
+
+    fn from_binary(bytes: &[u8]) -> Result<Self> {
+        let Some(body) = bytes.strip_prefix(MAGIC) else {
+            return Err(Error::Decode("not a tileset file".to_string()));
+        };
+        let mut words = body
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        let mut next = || {
+            words
+                .next()
+                .ok_or_else(|| Error::Decode("truncated tileset file".to_string()))
+        };
+
+        let version = next()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::Decode(format!(
+                "unsupported tileset version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let sample_hash = next()? as u64 | (next()? as u64) << 32;
+        let tile_width = next()? as usize;
+        let tile_height = next()? as usize;
+        let periodic = next()? != 0;
+        let symmetry = next()? as usize;
+        let ground = next()? as usize;
+        let palette = (0..next()?).map(|_| next()).collect::<Result<_>>()?;
+        let pattern_count = next()?;
+        let mut patterns = Vec::new();
+        for _ in 0..pattern_count {
+            let weight = next()?;
+            let pixels = (0..tile_width * tile_height)
+                .map(|_| {
+                    next().and_then(|pixel| {
+                        u16::try_from(pixel)
+                            .map_err(|_| Error::Decode(format!("invalid palette index {}", pixel)))
+                    })
+                })
+                .collect::<Result<_>>()?;
+            let mut neighbors: [Vec<usize>; 4] = Default::default();
+            for direction in neighbors.iter_mut() {
+                *direction = (0..next()?)
+                    .map(|_| next().map(|index| index as usize))
+                    .collect::<Result<_>>()?;
+            }
+            patterns.push(PatternData {
+                pixels,
+                weight,
+                neighbors,
+            });
+        }
+        Ok(TileSetData {
+            version,
+            sample_hash,
+            tile_width,
+            tile_height,
+            periodic,
+            symmetry,
+            ground,
+            palette,
+            patterns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn tileset() -> TileSet<2, 2> {
+        let colors = [Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([0, 128, 255])];
+        let sample = RgbImage::from_fn(5, 4, |x, y| colors[((x * y + x) % 3) as usize]);
+        TileSet::new(
+            &sample,
+            Extraction {
+                periodic: true,
+                symmetry: 2,
+            },
+        )
+        .unwrap()
+    }
+
+    fn assert_same(restored: &TileSet<2, 2>, tileset: &TileSet<2, 2>) {
+        assert_eq!(restored.extraction, tileset.extraction);
+        assert_eq!(restored.ground, tileset.ground);
+        assert_eq!(restored.tiles.len(), tileset.tiles.len());
+        for (restored, tile) in restored.tiles.iter().zip(&tileset.tiles) {
+            assert_eq!(restored.pixels, tile.pixels);
+            assert_eq!(restored.frequency, tile.frequency);
+            assert_eq!(restored.neighbors, tile.neighbors);
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let tileset = tileset();
+        let (restored, hash) = TileSet::from_bytes(&tileset.to_bytes(42).unwrap()).unwrap();
+        assert_eq!(hash, 42);
+        assert_same(&restored, &tileset);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let tileset = tileset();
+        let json = serde_json::to_vec(&tileset.to_data(42).unwrap()).unwrap();
+        let data: TileSetData = serde_json::from_slice(&json).unwrap();
+        assert_eq!(data.sample_hash, 42);
+        assert_same(&TileSet::from_data(data).unwrap(), &tileset);
+    }
+
+    #[test]
+    fn rejects_palette_indices_beyond_u16() {
+        let mut bytes = tileset().to_bytes(0).unwrap();
+        // The first pixel follows the palette, the pattern count and the
+        // weight of the first pattern.
+        let palette_len = u32::from_le_bytes(bytes[36..40].try_into().unwrap()) as usize;
+        let pixel = 40 + 4 * palette_len + 8;
+        bytes[pixel..pixel + 4].copy_from_slice(&0x1_0000u32.to_le_bytes());
+        assert!(matches!(
+            TileSet::<2, 2>::from_bytes(&bytes),
+            Err(Error::Decode(_))
+        ));
+    }
+}
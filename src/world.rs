@@ -0,0 +1,271 @@
+use crate::error::{Error, Result};
+use crate::grid::Grid;
+use crate::tileset::{SampleSpec, TileSet};
+use bittyset::BitSet;
+use image::RgbImage;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// How often a chunk is regenerated with a fresh seed before it gives up on
+/// some of its neighbor cells.
+const MAX_ATTEMPTS: u32 = 8;
+/// Cells generated around every chunk, see `World::grid`.
+const RING: usize = 6;
+
+/// An unbounded map generated chunk by chunk on demand.
+///
+/// Every chunk continues the neighbors that were generated before it,
+/// including the diagonal ones, so the map depends on the order in which
+/// chunks are asked for. Where the neighbors cannot be continued, e.g. where
+/// two of them meet at a corner no pattern fits, or no seed succeeds after
+/// [`MAX_ATTEMPTS`], the chunk gives up on the neighbor cells around the
+/// contradiction and tries again. One unlucky chunk thus leaves a short seam
+/// instead of failing the world.
+pub(crate) struct World<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    seed: u64,
+    chunk_width: usize,
+    chunk_height: usize,
+    /// A chunk with a ring of [`RING`] cells around it, which is dropped
+    /// afterwards. Where the chunk continues others the ring holds their
+    /// tiles; elsewhere it makes sure that the chunks continuing this one can
+    /// fit it, as edge cells of a grid may take patterns that only fit at an
+    /// edge.
+    grid: Grid<TILE_WIDTH, TILE_HEIGHT>,
+    chunks: HashMap<(i32, i32), Vec<usize>>,
+    /// Chunks that do not continue all neighbor cells around them, with the
+    /// number of cells they gave up on.
+    relaxed: Vec<((i32, i32), usize)>,
+}
+
+impl<const TILE_WIDTH: usize, const TILE_HEIGHT: usize> World<TILE_WIDTH, TILE_HEIGHT>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    pub(crate) fn new(
+        tileset: TileSet<TILE_WIDTH, TILE_HEIGHT>,
+        chunk_width: usize,
+        chunk_height: usize,
+        seed: u64,
+    ) -> Result<Self> {
+        let mut grid = Grid::with_seed(
+            tileset,
+            chunk_width + 2 * RING,
+            chunk_height + 2 * RING,
+            seed,
+        )?;
+        grid.set_periodic(false);
+        Ok(Self {
+            seed,
+            chunk_width,
+            chunk_height,
+            grid,
+            chunks: HashMap::new(),
+            relaxed: Vec::new(),
+        })
+    }
+
+    pub(crate) fn tileset(&self) -> &TileSet<TILE_WIDTH, TILE_HEIGHT> {
+        &self.grid.tileset
+    }
+
+    /// Returns the tiles of chunk `(cx, cy)` in row-major order, generating it
+    /// first if necessary. Fails only if the chunk cannot be generated even
+    /// without neighbors, i.e. if the tileset cannot fill a grid of its size.
+    pub(crate) fn chunk(&mut self, cx: i32, cy: i32) -> Result<&[usize]> {
+        if !self.chunks.contains_key(&(cx, cy)) {
+            let tiles = self.generate_chunk(cx, cy)?;
+            self.chunks.insert((cx, cy), tiles);
+        }
+        Ok(&self.chunks[&(cx, cy)])
+    }
+
+    /// Chunks generated so far that do not fit all their neighbors, with the
+    /// number of neighbor cells they do not continue.
+    fn relaxed(&self) -> &[((i32, i32), usize)] {
+        &self.relaxed
+    }
+
+    /// Returns the tile at world coordinates `(x, y)` if its chunk exists.
+    pub(crate) fn get_tile(&self, x: i64, y: i64) -> Option<usize> {
+        let (chunk_width, chunk_height) = (self.chunk_width, self.chunk_height);
+        let cx = x.div_euclid(chunk_width as i64) as i32;
+        let cy = y.div_euclid(chunk_height as i64) as i32;
+        let lx = x.rem_euclid(chunk_width as i64) as usize;
+        let ly = y.rem_euclid(chunk_height as i64) as usize;
+        self.chunks
+            .get(&(cx, cy))
+            .map(|tiles| tiles[lx + ly * chunk_width])
+    }
+
+    fn generate_chunk(&mut self, cx: i32, cy: i32) -> Result<Vec<usize>> {
+        let (chunk_width, chunk_height) = (self.chunk_width, self.chunk_height);
+        let (grid_width, grid_height) = (chunk_width + 2 * RING, chunk_height + 2 * RING);
+        // The ring cell, if any, next to cell `(x, y)` of the neighbor `(dx, dy)`.
+        let ring_index = |dx: i32, dy: i32, x: usize, y: usize| {
+            let x = (x + RING) as i64 + dx as i64 * chunk_width as i64;
+            let y = (y + RING) as i64 + dy as i64 * chunk_height as i64;
+            let inside =
+                (0..grid_width as i64).contains(&x) && (0..grid_height as i64).contains(&y);
+            inside.then(|| x as usize + y as usize * grid_width)
+        };
+        let mut pinned = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let Some(tiles) = self.chunks.get(&(cx + dx, cy + dy)) else {
+                    continue;
+                };
+                for (index, &tile) in tiles.iter().enumerate() {
+                    if let Some(ring) = ring_index(dx, dy, index % chunk_width, index / chunk_width)
+                    {
+                        pinned.push((ring, tile));
+                    }
+                }
+            }
+        }
+
+        let all_pinned = pinned.len();
+        let (mut attempt, mut failures) = (0, 0);
+        loop {
+            self.grid.reset(chunk_seed(self.seed, cx, cy, attempt));
+            attempt += 1;
+            // Pinning does not depend on the seed, so if it contradicts, only
+            // giving up on pins helps.
+            let index = match pin(&mut self.grid, &pinned) {
+                Err(Error::Contradiction { index }) => index,
+                Err(e) => return Err(e),
+                Ok(()) => match self.grid.run_to_completion() {
+                    Ok(()) => break,
+                    Err(Error::Contradiction { index }) => {
+                        failures += 1;
+                        if failures < MAX_ATTEMPTS {
+                            continue;
+                        }
+                        if pinned.is_empty() {
+                            return Err(Error::Contradiction { index });
+                        }
+                        index
+                    }
+                    Err(e) => return Err(e),
+                },
+            };
+            failures = 0;
+            unpin_around(&mut pinned, index, grid_width);
+        }
+        if pinned.len() < all_pinned {
+            self.relaxed.push(((cx, cy), all_pinned - pinned.len()));
+        }
+        Ok((0..chunk_width * chunk_height)
+            .map(|index| {
+                let x = index % chunk_width + RING;
+                let y = index / chunk_width + RING;
+                self.grid
+                    .get_final_tile(x + y * grid_width)
+                    .expect("a generated chunk is collapsed")
+            })
+            .collect())
+    }
+}
+
+/// Drops the pins around cell `index` of a grid `width` cells wide: those
+/// within [`RING`] cells, or the closest ones if none is that close.
+fn unpin_around(pinned: &mut Vec<(usize, usize)>, index: usize, width: usize) {
+    let (x, y) = (index % width, index / width);
+    let distance =
+        |&(pin, _): &(usize, usize)| (pin % width).abs_diff(x).max((pin / width).abs_diff(y));
+    let nearest = pinned.iter().map(distance).min().unwrap_or(0);
+    let radius = nearest.max(RING);
+    pinned.retain(|pin| distance(pin) > radius);
+}
+
+/// Restricts every `(index, tile)` cell of `grid` to its tile.
+fn pin<const TILE_WIDTH: usize, const TILE_HEIGHT: usize>(
+    grid: &mut Grid<TILE_WIDTH, TILE_HEIGHT>,
+    cells: &[(usize, usize)],
+) -> Result<()>
+where
+    [(); TILE_WIDTH * TILE_HEIGHT]:,
+{
+    for &(index, tile) in cells {
+        let only_tile: BitSet = [tile].into_iter().collect();
+        grid.constrain(index, &only_tile)?;
+    }
+    Ok(())
+}
+
+/// Derives the seed of one chunk attempt from the world seed (splitmix64).
+fn chunk_seed(seed: u64, cx: i32, cy: i32, attempt: u32) -> u64 {
+    let mut z = seed
+        ^ (cx as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (cy as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (attempt as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// The chunks of a world that `run` saves.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Region {
+    /// Cells of every chunk.
+    pub(crate) chunk_size: (usize, usize),
+    /// The chunk at the top left.
+    pub(crate) origin: (i32, i32),
+    /// Columns and rows of chunks.
+    pub(crate) chunks: (usize, usize),
+    /// Whether the chunks are generated from the bottom right instead.
+    pub(crate) reverse: bool,
+}
+
+/// Generates the chunks of `region` of the world with `seed`, or a random
+/// one, and saves them as one image with a pixel per cell.
+pub(crate) fn run<const N: usize>(
+    output: &Path,
+    samples: &[SampleSpec],
+    symmetry: usize,
+    seed: Option<u64>,
+    cache_dir: &Path,
+    region: Region,
+) -> Result<()>
+where
+    [(); N * N]:,
+{
+    let tileset = TileSet::<N, N>::from_samples_cached(samples, symmetry, cache_dir)?;
+    let seed = seed.unwrap_or_else(rand::random);
+    println!("world seed {}", seed);
+    let (chunk_width, chunk_height) = region.chunk_size;
+    let mut world = World::new(tileset, chunk_width, chunk_height, seed)?;
+    let ((origin_x, origin_y), (columns, rows)) = (region.origin, region.chunks);
+    let mut order: Vec<(i32, i32)> = (0..rows as i32)
+        .flat_map(|y| (0..columns as i32).map(move |x| (origin_x + x, origin_y + y)))
+        .collect();
+    if region.reverse {
+        order.reverse();
+    }
+    for (cx, cy) in order {
+        world.chunk(cx, cy)?;
+    }
+    for &((cx, cy), cells) in world.relaxed() {
+        eprintln!(
+            "WARNING: chunk {},{} does not continue {} cells of its neighbors",
+            cx, cy, cells
+        );
+    }
+
+    let left = origin_x as i64 * chunk_width as i64;
+    let top = origin_y as i64 * chunk_height as i64;
+    let image = RgbImage::from_fn(
+        (columns * chunk_width) as u32,
+        (rows * chunk_height) as u32,
+        |x, y| {
+            let tile = world
+                .get_tile(left + x as i64, top + y as i64)
+                .expect("all chunks of the image are generated");
+            world.tileset().get_tile(tile).get_rgb()
+        },
+    );
+    image.save(output).map_err(|e| Error::image(output, e))
+}